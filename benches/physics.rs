@@ -0,0 +1,62 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use minigolf::gamemap::{Assets, GameMap};
+use minigolf::magnet::{Magnet, MagnetForces};
+use minigolf::map::Map;
+use minigolf::tile::{Element, Special, Tile};
+
+fn magnet_map(count: usize) -> Map {
+    let mut map = Map::new();
+    for i in 0..count {
+        let special = if i % 2 == 0 {
+            Special::MagnetAttract
+        } else {
+            Special::MagnetRepel
+        };
+        let tile = Tile::new(Some(special), None, Element::Grass, Element::Grass);
+        map.set_tile((i * 7) % Map::WIDTH, (i * 5) % Map::HEIGHT, tile)
+            .unwrap();
+    }
+    map
+}
+
+//A sprite sheet with a filled circle in every sprite so both branches get exercised
+fn write_sheet() -> tempfile::NamedTempFile {
+    let size = Map::TILESIZE as u32;
+    let mut image = image::RgbaImage::new(size * 28, size);
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let dx = (x % size) as i32 - 7;
+        let dy = y as i32 - 7;
+        let alpha = if dx * dx + dy * dy <= 36 { 255 } else { 0 };
+        *pixel = image::Rgba([0, 0, 0, alpha]);
+    }
+    let file = tempfile::Builder::new().suffix(".png").tempfile().unwrap();
+    image.save(file.path()).unwrap();
+    file
+}
+
+fn magnet_forces(c: &mut Criterion) {
+    let mut group = c.benchmark_group("calculate_forces");
+    for count in [1, 5, 20] {
+        let magnets = Magnet::extract_magnets(&magnet_map(count));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(count),
+            &magnets,
+            |b, magnets| b.iter(|| MagnetForces::new(black_box(magnets))),
+        );
+    }
+    group.finish();
+}
+
+fn game_map(c: &mut Criterion) {
+    let sheet = write_sheet();
+    let path = sheet.path().to_str().unwrap();
+    let assets = Assets::load(path, path).unwrap();
+    let track = minigolf::track::Track::from_filepath("testi.track").unwrap();
+
+    c.bench_function("GameMap::from_map", |b| {
+        b.iter(|| GameMap::from_map(black_box(&track.map), &assets))
+    });
+}
+
+criterion_group!(benches, magnet_forces, game_map);
+criterion_main!(benches);
@@ -0,0 +1,371 @@
+use crate::direction::Direction4;
+use crate::gamemap::{Assets, GameMap, PushResult};
+use crate::magnet::MagnetForces;
+use crate::map::Map;
+use crate::stroke::PhysicsConfig;
+use crate::tile::Special;
+use crate::vector2d::Vector2D;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Ball {
+    pub position: Vector2D<f32>,
+    pub velocity: Vector2D<f32>,
+    //Positive spin curves the ball counterclockwise, only with config.enable_spin
+    pub spin: f32,
+    pub config: PhysicsConfig,
+    teleport_cooldown: u32,
+}
+
+impl Ball {
+    //Steps after a teleport before the ball can be teleported again
+    pub const TELEPORT_COOLDOWN: u32 = 10;
+
+    //Below this speed a ball on flat ground stops
+    pub const REST_SPEED: f32 = 0.01;
+
+    //Sideways acceleration per unit of spin and speed
+    pub const SPIN_CURVE: f32 = 0.02;
+
+    //Fraction of spin left after each step
+    pub const SPIN_DECAY: f32 = 0.95;
+
+    //Spin gained per unit of speed along the surface in a bounce
+    pub const SPIN_TRANSFER: f32 = 0.5;
+
+    //Fraction of the speed kept after pushing a moveable block
+    pub const PUSH_SPEED_KEPT: f32 = 0.5;
+
+    pub fn new(position: Vector2D<f32>) -> Self {
+        Ball::with_config(position, PhysicsConfig::default())
+    }
+
+    pub fn with_config(position: Vector2D<f32>, config: PhysicsConfig) -> Self {
+        Self {
+            position,
+            velocity: Vector2D::zero(),
+            spin: 0.0,
+            config,
+            teleport_cooldown: 0,
+        }
+    }
+
+    pub fn teleport_cooldown(&self) -> u32 {
+        self.teleport_cooldown
+    }
+
+    //Moves the ball one step, slows it down by the friction under it and
+    //handles teleports. Collisions are not handled here.
+    pub fn step(&mut self, game_map: &GameMap) {
+        if self.config.enable_spin {
+            self.velocity += self.velocity.perpendicular_ccw() * (self.spin * Ball::SPIN_CURVE);
+            self.spin *= Ball::SPIN_DECAY;
+        }
+        self.position += self.velocity;
+
+        if let Some((x, y)) = self.pixel() {
+            self.velocity = self.velocity * game_map.friction_at(x, y);
+            self.velocity += game_map.downhill_at(x, y);
+        }
+        if self.is_resting(game_map) {
+            self.velocity = Vector2D::zero();
+        }
+
+        if self.teleport_cooldown > 0 {
+            self.teleport_cooldown -= 1;
+        } else {
+            self.try_teleport(game_map);
+        }
+    }
+
+    //Reflects the velocity off a surface facing normal. With spin enabled the
+    //speed along the surface becomes spin, so glancing hits spin the most.
+    pub fn bounce(&mut self, normal: Vector2D<f32>) {
+        let normal = normal.normalize();
+        let tangent = normal.perpendicular_ccw();
+        let dot = |a: Vector2D<f32>, b: Vector2D<f32>| a.x * b.x + a.y * b.y;
+        let along_tangent = dot(self.velocity, tangent);
+        self.velocity = self.velocity - normal * (2.0 * dot(self.velocity, normal));
+        if self.config.enable_spin {
+            self.spin = along_tangent * Ball::SPIN_TRANSFER;
+        }
+    }
+
+    //Same as step, but a moveable block in the way of the next move gets pushed
+    //along the main axis of movement. Pushing slows the ball down, a block that
+    //can't move bounces it back.
+    pub fn step_pushing(&mut self, game_map: &mut GameMap, map: &mut Map, assets: &Assets) {
+        let next = self.position + self.velocity;
+        let block = Ball::pixel_of(next)
+            .filter(|&pixel| Some(pixel) != self.pixel())
+            .filter(|&(x, y)| {
+                game_map.get_tile(x, y).is_some_and(|tile| {
+                    matches!(
+                        tile.as_special(),
+                        Some(Special::MoveableBlock | Special::MoveableBlock2)
+                    )
+                })
+            })
+            .map(|(x, y)| (x / Map::TILESIZE, y / Map::TILESIZE));
+
+        if let Some(tile) = block {
+            let direction = if self.velocity.x.abs() >= self.velocity.y.abs() {
+                if self.velocity.x > 0.0 {
+                    Direction4::E
+                } else {
+                    Direction4::W
+                }
+            } else if self.velocity.y > 0.0 {
+                Direction4::S
+            } else {
+                Direction4::N
+            };
+            match game_map.try_push_block(map, assets, tile, direction) {
+                Some(PushResult::Moved) => self.velocity = self.velocity * Ball::PUSH_SPEED_KEPT,
+                Some(PushResult::Blocked) => {
+                    let normal = direction.opposite().to_vector();
+                    self.bounce(Vector2D::new(normal.x as f32, normal.y as f32));
+                }
+                None => {}
+            }
+        }
+        self.step(game_map);
+    }
+
+    //Same as step with the pull of the magnets added to the velocity first
+    pub fn step_with_magnets(&mut self, game_map: &GameMap, magnets: &MagnetForces) {
+        self.velocity += magnets.get_force_at(self.position) * MagnetForces::SCALE;
+        self.step(game_map);
+    }
+
+    //Slow enough to stop and not on a tile that keeps accelerating it
+    pub fn is_resting(&self, game_map: &GameMap) -> bool {
+        let accelerating = self
+            .pixel()
+            .is_some_and(|(x, y)| game_map.downhill_at(x, y).length() > 0.0);
+        self.velocity.length() < Ball::REST_SPEED && !accelerating
+    }
+
+    //A ball on a teleport start moves to the center of the first matching exit,
+    //keeping its velocity
+    fn try_teleport(&mut self, game_map: &GameMap) {
+        let exit = self
+            .pixel()
+            .and_then(|(x, y)| game_map.teleport_exit((x / Map::TILESIZE, y / Map::TILESIZE)));
+        if let Some((x, y)) = exit {
+            self.position = Ball::tile_center(x, y);
+            self.teleport_cooldown = Ball::TELEPORT_COOLDOWN;
+        }
+    }
+
+    fn pixel(&self) -> Option<(usize, usize)> {
+        Ball::pixel_of(self.position)
+    }
+
+    fn pixel_of(position: Vector2D<f32>) -> Option<(usize, usize)> {
+        if position.x < 0.0 || position.y < 0.0 {
+            return None;
+        }
+        Some((position.x as usize, position.y as usize))
+    }
+
+    pub fn tile_center(x: usize, y: usize) -> Vector2D<f32> {
+        let half = Map::TILESIZE as f32 / 2.0;
+        Vector2D::new(
+            (x * Map::TILESIZE) as f32 + half,
+            (y * Map::TILESIZE) as f32 + half,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gamemap::GameMapTile;
+    use crate::tile::{Element, Tile};
+    use std::collections::{HashMap, HashSet};
+
+    fn grass_game_map(map: &Map) -> GameMap {
+        let mut game_map = GameMap {
+            tiles: vec![GameMapTile::Element(Element::Grass); GameMap::WIDTH * GameMap::HEIGHT],
+            teleports: HashMap::new(),
+            illusions: HashSet::new(),
+            holes: map.find_all_special(Special::Hole),
+        };
+        game_map.rebuild_teleports(map);
+        game_map
+    }
+
+    fn special(special: Special) -> Tile {
+        Tile::new(Some(special), None, Element::Grass, Element::Grass)
+    }
+
+    #[test]
+    fn test_teleport() {
+        let mut map = Map::new();
+        map.set_tile(5, 5, special(Special::BlueTeleportStart))
+            .unwrap();
+        map.set_tile(20, 10, special(Special::BlueTeleportExit))
+            .unwrap();
+        map.set_tile(30, 10, special(Special::RedTeleportExit))
+            .unwrap();
+        let game_map = grass_game_map(&map);
+
+        let mut ball = Ball::new(Ball::tile_center(5, 5) - Vector2D::new(1.0, 0.0));
+        ball.velocity = Vector2D::new(1.0, 0.0);
+        ball.step(&game_map);
+
+        let friction = Element::Grass.get_friction();
+        assert_eq!(ball.position.x, Ball::tile_center(20, 10).x);
+        assert_eq!(ball.position.y, Ball::tile_center(20, 10).y);
+        assert_eq!(ball.velocity.x, friction);
+        assert_eq!(ball.velocity.y, 0.0);
+        assert_eq!(ball.teleport_cooldown(), Ball::TELEPORT_COOLDOWN);
+    }
+
+    #[test]
+    fn test_teleport_cooldown() {
+        let mut map = Map::new();
+        map.set_tile(5, 5, special(Special::RedTeleportStart))
+            .unwrap();
+        map.set_tile(6, 5, special(Special::RedTeleportStart))
+            .unwrap();
+        map.set_tile(20, 10, special(Special::RedTeleportExit))
+            .unwrap();
+        let game_map = grass_game_map(&map);
+
+        let mut ball = Ball::new(Ball::tile_center(5, 5));
+        ball.teleport_cooldown = 1;
+        ball.step(&game_map);
+        assert_eq!(ball.position.x, Ball::tile_center(5, 5).x);
+        assert_eq!(ball.teleport_cooldown(), 0);
+
+        ball.step(&game_map);
+        assert_eq!(ball.position.x, Ball::tile_center(20, 10).x);
+    }
+
+    #[test]
+    fn test_no_exit() {
+        let mut map = Map::new();
+        map.set_tile(5, 5, special(Special::GreenTeleportStart))
+            .unwrap();
+        let mut ball = Ball::new(Ball::tile_center(5, 5));
+        ball.step(&grass_game_map(&map));
+        assert_eq!(ball.position.x, Ball::tile_center(5, 5).x);
+        assert_eq!(ball.teleport_cooldown(), 0);
+    }
+
+    #[test]
+    fn test_resting() {
+        let mut game_map = grass_game_map(&Map::new());
+        //Tile (5, 5) slopes down to the south
+        for y in 75..90 {
+            for x in 75..90 {
+                game_map.tiles[y * GameMap::WIDTH + x] = GameMapTile::Element(Element::SpeedS);
+            }
+        }
+
+        let mut grass = Ball::new(Ball::tile_center(20, 20));
+        grass.velocity = Vector2D::new(0.005, 0.0);
+        assert!(grass.is_resting(&game_map));
+        grass.step(&game_map);
+        assert_eq!((grass.velocity.x, grass.velocity.y), (0.0, 0.0));
+
+        let mut slope = Ball::new(Ball::tile_center(5, 5) - Vector2D::new(0.0, 7.0));
+        assert!(!slope.is_resting(&game_map));
+        let start = slope.position.y;
+        for _ in 0..5 {
+            slope.step(&game_map);
+        }
+        assert!(slope.velocity.y > 0.0);
+        assert!(slope.position.y > start);
+        assert_eq!(slope.velocity.x, 0.0);
+    }
+
+    #[test]
+    fn test_step_with_magnets() {
+        let game_map = grass_game_map(&Map::new());
+        let mut map = Map::new();
+        map.set_tile(10, 5, special(Special::MagnetAttract))
+            .unwrap();
+        let magnets = MagnetForces::new(&crate::magnet::Magnet::extract_magnets(&map));
+
+        let start = Ball::tile_center(5, 5);
+        let mut pulled = Ball::new(start);
+        pulled.step_with_magnets(&game_map, &magnets);
+        assert!(pulled.velocity.x > 0.0);
+
+        let mut free = Ball::new(start);
+        free.step_with_magnets(&game_map, &MagnetForces::new(&[]));
+        assert_eq!((free.velocity.x, free.velocity.y), (0.0, 0.0));
+    }
+
+    #[test]
+    fn test_spin() {
+        let game_map = grass_game_map(&Map::new());
+        let spin = PhysicsConfig {
+            enable_spin: true,
+            ..PhysicsConfig::default()
+        };
+        let mut plain = Ball::new(Ball::tile_center(20, 20));
+        let mut spinning = Ball::with_config(Ball::tile_center(20, 20), spin);
+
+        //Hitting a wall to the east at an angle
+        for ball in [&mut plain, &mut spinning] {
+            ball.velocity = Vector2D::new(2.0, 1.0);
+            ball.bounce(Vector2D::new(-1.0, 0.0));
+            assert_eq!((ball.velocity.x, ball.velocity.y), (-2.0, 1.0));
+        }
+        assert_eq!(plain.spin, 0.0);
+        assert!(spinning.spin != 0.0);
+
+        for _ in 0..10 {
+            plain.step(&game_map);
+            spinning.step(&game_map);
+        }
+        assert!(!plain.position.approx_eq(&spinning.position, 0.01));
+        assert!(spinning.spin.abs() < Ball::SPIN_TRANSFER);
+
+        //Without spin the path is a straight line
+        let start = Ball::tile_center(20, 20);
+        let offset = plain.position - start;
+        assert!((offset.x * 1.0 + offset.y * 2.0).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_step_pushing() {
+        let assets = Assets::procedural(Map::TILESIZE);
+        let mut map = Map::new();
+        map.set_tile(10, 5, special(Special::MoveableBlock))
+            .unwrap();
+        let mut game_map = GameMap::from_map(&map, &assets);
+        let start = Vector2D::new(148.0, Ball::tile_center(9, 5).y);
+
+        let mut ball = Ball::new(start);
+        ball.velocity = Vector2D::new(3.0, 0.0);
+        ball.step_pushing(&mut game_map, &mut map, &assets);
+        assert_eq!(
+            map.get_tile(11, 5).unwrap().special,
+            Some(Special::MoveableBlock)
+        );
+        assert_eq!(map.get_tile(10, 5).unwrap().special, None);
+        assert!(ball.velocity.x > 0.0);
+        assert!(ball.velocity.x <= 3.0 * Ball::PUSH_SPEED_KEPT);
+
+        //A block against a wall stays and the ball bounces back
+        let mut map = Map::new();
+        map.set_tile(10, 5, special(Special::MoveableBlock))
+            .unwrap();
+        map.set_tile(11, 5, Tile::default().with_background(Element::Block))
+            .unwrap();
+        let mut game_map = GameMap::from_map(&map, &assets);
+        let mut ball = Ball::new(start);
+        ball.velocity = Vector2D::new(3.0, 0.0);
+        ball.step_pushing(&mut game_map, &mut map, &assets);
+        assert_eq!(
+            map.get_tile(10, 5).unwrap().special,
+            Some(Special::MoveableBlock)
+        );
+        assert!(ball.velocity.x < 0.0);
+        assert!(ball.position.x < start.x);
+    }
+}
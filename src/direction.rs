@@ -0,0 +1,167 @@
+use crate::vector2d::Vector2D;
+
+//Compass directions in screen coordinates, north is up so y grows to the south
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Hash)]
+pub enum Direction4 {
+    N,
+    E,
+    S,
+    W,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Hash)]
+pub enum Direction8 {
+    N,
+    NE,
+    E,
+    SE,
+    S,
+    SW,
+    W,
+    NW,
+}
+
+impl Direction4 {
+    pub fn all() -> &'static [Direction4] {
+        const ALL: [Direction4; 4] = [Direction4::N, Direction4::E, Direction4::S, Direction4::W];
+        &ALL
+    }
+
+    pub fn to_vector(&self) -> Vector2D<i32> {
+        Direction8::from(*self).to_vector()
+    }
+
+    pub fn opposite(&self) -> Direction4 {
+        self.rotate(2)
+    }
+
+    pub fn rotate_cw(&self) -> Direction4 {
+        self.rotate(1)
+    }
+
+    pub fn rotate_ccw(&self) -> Direction4 {
+        self.rotate(3)
+    }
+
+    fn rotate(&self, steps: usize) -> Direction4 {
+        Direction4::all()[(*self as usize + steps) % 4]
+    }
+}
+
+impl Direction8 {
+    pub fn all() -> &'static [Direction8] {
+        const ALL: [Direction8; 8] = [
+            Direction8::N,
+            Direction8::NE,
+            Direction8::E,
+            Direction8::SE,
+            Direction8::S,
+            Direction8::SW,
+            Direction8::W,
+            Direction8::NW,
+        ];
+        &ALL
+    }
+
+    pub fn to_vector(&self) -> Vector2D<i32> {
+        let (x, y) = match self {
+            Direction8::N => (0, -1),
+            Direction8::NE => (1, -1),
+            Direction8::E => (1, 0),
+            Direction8::SE => (1, 1),
+            Direction8::S => (0, 1),
+            Direction8::SW => (-1, 1),
+            Direction8::W => (-1, 0),
+            Direction8::NW => (-1, -1),
+        };
+        Vector2D::new(x, y)
+    }
+
+    pub fn is_diagonal(&self) -> bool {
+        matches!(
+            self,
+            Direction8::NE | Direction8::SE | Direction8::SW | Direction8::NW
+        )
+    }
+
+    pub fn opposite(&self) -> Direction8 {
+        self.rotate(4)
+    }
+
+    //Rotates by 45 degrees
+    pub fn rotate_cw(&self) -> Direction8 {
+        self.rotate(1)
+    }
+
+    pub fn rotate_ccw(&self) -> Direction8 {
+        self.rotate(7)
+    }
+
+    fn rotate(&self, steps: usize) -> Direction8 {
+        Direction8::all()[(*self as usize + steps) % 8]
+    }
+}
+
+impl From<Direction4> for Direction8 {
+    fn from(direction: Direction4) -> Self {
+        match direction {
+            Direction4::N => Direction8::N,
+            Direction4::E => Direction8::E,
+            Direction4::S => Direction8::S,
+            Direction4::W => Direction8::W,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_vector() {
+        let expected = [
+            (0, -1),
+            (1, -1),
+            (1, 0),
+            (1, 1),
+            (0, 1),
+            (-1, 1),
+            (-1, 0),
+            (-1, -1),
+        ];
+        for (direction, (x, y)) in Direction8::all().iter().zip(expected) {
+            let vector = direction.to_vector();
+            assert_eq!((vector.x, vector.y), (x, y));
+        }
+        for direction in Direction4::all() {
+            let vector = direction.to_vector();
+            assert_eq!(vector.x.abs() + vector.y.abs(), 1);
+        }
+        assert_eq!(Direction4::W.to_vector().x, -1);
+    }
+
+    #[test]
+    fn test_opposite() {
+        for direction in Direction8::all() {
+            let vector = direction.to_vector();
+            let opposite = direction.opposite().to_vector();
+            assert_eq!((opposite.x, opposite.y), (-vector.x, -vector.y));
+            assert_eq!(direction.opposite().opposite(), *direction);
+        }
+        assert_eq!(Direction4::N.opposite(), Direction4::S);
+        assert_eq!(Direction4::E.opposite(), Direction4::W);
+        assert_eq!(Direction8::NE.opposite(), Direction8::SW);
+    }
+
+    #[test]
+    fn test_rotate() {
+        assert_eq!(Direction4::N.rotate_cw(), Direction4::E);
+        assert_eq!(Direction4::W.rotate_cw(), Direction4::N);
+        assert_eq!(Direction4::N.rotate_ccw(), Direction4::W);
+        assert_eq!(Direction8::NW.rotate_cw(), Direction8::N);
+        assert_eq!(Direction8::N.rotate_ccw(), Direction8::NW);
+        for direction in Direction8::all() {
+            assert_eq!(direction.rotate_cw().rotate_ccw(), *direction);
+        }
+    }
+}
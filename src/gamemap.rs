@@ -1,61 +1,200 @@
-use crate::array2diter::Array2DRangeIterator;
+use crate::array2diter::Array2DIterator;
+use crate::direction::Direction4;
 use crate::map::Map;
 use crate::tile::{Element, Shape, Special, Tile};
+use crate::track::Settings;
 use crate::vector2d::Vector2D;
-use image::GenericImageView;
-use image::ImageError;
-use image::Pixel;
+#[cfg(feature = "image")]
+use image::{DynamicImage, GenericImageView, ImageError, Pixel};
+use num_traits::FromPrimitive;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::num::TryFromIntError;
+#[cfg(any(feature = "image", test))]
+use std::sync::OnceLock;
 use thiserror::Error;
 
 //Used in physics
 pub struct GameMap {
     pub tiles: Vec<GameMapTile>,
+    //Teleport start tile to its exit tile, in map tiles
+    pub teleports: HashMap<(usize, usize), (usize, usize)>,
+    //Map tiles of fake blocks that get a shadow like real blocks, see
+    //from_map_with_settings. Only for drawing, they stay passable.
+    pub illusions: HashSet<(usize, usize)>,
+    //Map tiles with a real hole
+    pub holes: Vec<(usize, usize)>,
 }
-#[derive(Debug)]
+//What a single pixel resolves to. Friction follows the resolved value: a pixel
+//covered by a special's sprite uses the special's friction, every other pixel
+//uses its element's, including specials that fall back to the background
+//(fake holes, teleport exits, repelling magnets, magnets in liquid).
+#[derive(Debug, PartialEq, Copy, Clone)]
 pub enum GameMapTile {
     Special(Special),
     Element(Element),
 }
 
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum PushResult {
+    Moved,
+    //Something is in the way and the block stays put
+    Blocked,
+}
+
 #[derive(Debug, Error)]
 pub enum AssetError {
+    #[cfg(feature = "image")]
     #[error("Image Error:{0}")]
     ImageError(#[from] ImageError),
     #[error("Try From Int Error{0}")]
     TryFromIntError(#[from] TryFromIntError),
+    #[error("Sprite sheet is {got:?}, expected at least {expected:?}")]
+    DimensionMismatch {
+        expected: (u32, u32),
+        got: (u32, u32),
+    },
 }
 
+#[derive(Debug, PartialEq)]
 pub struct Asset {
     sprites: Vec<Vec<bool>>,
+    tile_size: usize,
+    //Some(covered) for sprites that are the same on every pixel
+    coverage: Vec<Option<bool>>,
 }
 
+#[derive(Debug, PartialEq)]
 pub struct Assets {
     specials: Asset,
     shapes: Asset,
 }
 
+impl GameMapTile {
+    pub fn get_friction(&self) -> f32 {
+        match self {
+            GameMapTile::Special(special) => special.get_friction(),
+            GameMapTile::Element(element) => element.get_friction(),
+        }
+    }
+
+    pub fn as_element(&self) -> Option<Element> {
+        match self {
+            GameMapTile::Element(element) => Some(*element),
+            GameMapTile::Special(_) => None,
+        }
+    }
+
+    pub fn as_special(&self) -> Option<Special> {
+        match self {
+            GameMapTile::Special(special) => Some(*special),
+            GameMapTile::Element(_) => None,
+        }
+    }
+
+    pub fn is_element(&self) -> bool {
+        self.as_element().is_some()
+    }
+
+    pub fn is_special(&self) -> bool {
+        self.as_special().is_some()
+    }
+
+    pub fn is_solid(&self) -> bool {
+        self.as_element().is_some_and(|element| element.is_solid())
+            || self
+                .as_special()
+                .is_some_and(|special| special.blocks_ball())
+    }
+}
+
 impl Assets {
     pub const SHAPEPATH: &str = "./assets/shapes.png";
     pub const SPECIALPATH: &str = "./assets/specials.png";
 
+    #[cfg(feature = "image")]
     pub fn new() -> Result<Self, AssetError> {
-        let specials = Asset::load(Assets::SPECIALPATH, 28)?;
-        let shapes = Asset::load(Assets::SHAPEPATH, 28)?;
+        Assets::load(Assets::SPECIALPATH, Assets::SHAPEPATH)
+    }
+
+    #[cfg(feature = "image")]
+    pub fn load(special_path: &str, shape_path: &str) -> Result<Self, AssetError> {
+        let specials = Asset::load(special_path, 28)?;
+        let shapes = Asset::load(shape_path, 28)?;
         Ok(Self { specials, shapes })
     }
+
+    //Builds the sprites from geometry instead of the sprite sheets. Close to but
+    //not pixel exact with the originals, for use without the image feature.
+    pub fn procedural(tile_size: usize) -> Self {
+        Self {
+            specials: Asset::procedural(28, tile_size, |i, x, y| {
+                Special::from_usize(i).is_some_and(|special| special_covers(special, x, y))
+            }),
+            shapes: Asset::procedural(28, tile_size, |i, x, y| {
+                Shape::from_usize(i).is_some_and(|shape| shape_covers(shape, x, y))
+            }),
+        }
+    }
+
+    //Loads the sprite sheets from the default paths once and reuses them.
+    //A failed load is not cached, so later calls try again.
+    #[cfg(feature = "image")]
+    pub fn shared() -> Result<&'static Assets, AssetError> {
+        static SHARED: OnceLock<Assets> = OnceLock::new();
+        Assets::shared_in(&SHARED, Assets::new)
+    }
+
+    #[cfg(any(feature = "image", test))]
+    fn shared_in(
+        lock: &'static OnceLock<Assets>,
+        load: impl FnOnce() -> Result<Assets, AssetError>,
+    ) -> Result<&'static Assets, AssetError> {
+        if let Some(assets) = lock.get() {
+            return Ok(assets);
+        }
+        let assets = load()?;
+        Ok(lock.get_or_init(|| assets))
+    }
 }
 
 impl Asset {
+    #[cfg(feature = "image")]
     pub fn load(path: &str, len: usize) -> Result<Self, AssetError> {
+        Asset::load_with_size(path, len, Map::TILESIZE)
+    }
+
+    #[cfg(feature = "image")]
+    pub fn load_with_size(path: &str, len: usize, tile_size: usize) -> Result<Self, AssetError> {
         let image = image::open(path)?;
+        Asset::from_image(&image, len, tile_size)
+    }
+
+    //Same as load_with_size but from an encoded image, e.g. a PNG in memory
+    #[cfg(feature = "image")]
+    pub fn load_from_bytes(bytes: &[u8], len: usize, tile_size: usize) -> Result<Self, AssetError> {
+        let image = image::load_from_memory(bytes)?;
+        Asset::from_image(&image, len, tile_size)
+    }
+
+    #[cfg(feature = "image")]
+    pub fn from_image(
+        image: &DynamicImage,
+        len: usize,
+        tile_size: usize,
+    ) -> Result<Self, AssetError> {
+        let expected: (u32, u32) = ((len * tile_size).try_into()?, tile_size.try_into()?);
+        let got = image.dimensions();
+        if got.0 < expected.0 || got.1 < expected.1 {
+            return Err(AssetError::DimensionMismatch { expected, got });
+        }
+
         let mut sprites = Vec::new();
 
         for i in 0..len {
             let mut sprite = Vec::new();
-            for y in 0..Map::TILESIZE {
-                for x in 0..Map::TILESIZE {
-                    let x_pos: u32 = (i * Map::TILESIZE + x).try_into()?;
+            for y in 0..tile_size {
+                for x in 0..tile_size {
+                    let x_pos: u32 = (i * tile_size + x).try_into()?;
                     let y_pos: u32 = y.try_into()?;
                     let pixel = image.get_pixel(x_pos, y_pos).to_rgba();
                     sprite.push(pixel[3] != 0);
@@ -63,7 +202,51 @@ impl Asset {
             }
             sprites.push(sprite);
         }
-        Ok(Self { sprites })
+        Ok(Asset::from_sprites(sprites, tile_size))
+    }
+
+    //covers gets the sprite index and the pixel center scaled to 0.0..1.0
+    pub fn procedural(
+        len: usize,
+        tile_size: usize,
+        covers: impl Fn(usize, f32, f32) -> bool,
+    ) -> Self {
+        let sprites = (0..len)
+            .map(|i| {
+                (0..tile_size * tile_size)
+                    .map(|pix| {
+                        let x = (pix % tile_size) as f32 + 0.5;
+                        let y = (pix / tile_size) as f32 + 0.5;
+                        covers(i, x / tile_size as f32, y / tile_size as f32)
+                    })
+                    .collect()
+            })
+            .collect();
+        Asset::from_sprites(sprites, tile_size)
+    }
+
+    pub fn from_sprites(sprites: Vec<Vec<bool>>, tile_size: usize) -> Self {
+        let coverage = sprites
+            .iter()
+            .map(|sprite| match sprite.first() {
+                Some(&first) if sprite.iter().all(|&pixel| pixel == first) => Some(first),
+                _ => None,
+            })
+            .collect();
+        Self {
+            sprites,
+            tile_size,
+            coverage,
+        }
+    }
+
+    //Some(true) if the sprite covers its whole tile, Some(false) if it covers nothing
+    pub fn coverage(&self, i: usize) -> Option<bool> {
+        self.coverage.get(i).copied().flatten()
+    }
+
+    pub fn tile_size(&self) -> usize {
+        self.tile_size
     }
 
     pub fn get(&self, i: usize) -> Option<Vec<bool>> {
@@ -71,13 +254,80 @@ impl Asset {
     }
 
     pub fn get_bool_xy(&self, i: usize, x: usize, y: usize) -> bool {
-        let pix = y * Map::TILESIZE + x;
+        let pix = y * self.tile_size + x;
         self.get(i).unwrap().get(pix).unwrap().to_owned()
     }
+
+    //Nearest sprite pixel to fx and fy, both 0.0..1.0 across the tile
+    pub fn sample_at(&self, i: usize, fx: f32, fy: f32) -> bool {
+        let last = self.tile_size - 1;
+        let pixel = |f: f32| ((f.clamp(0.0, 1.0) * self.tile_size as f32) as usize).min(last);
+        self.get_bool_xy(i, pixel(fx), pixel(fy))
+    }
+
+    //Like get_bool_xy but x and y are in map tile pixels, scaled to the sprite size
+    pub fn sample(&self, i: usize, x: usize, y: usize) -> bool {
+        self.get_bool_xy(
+            i,
+            x * self.tile_size / Map::TILESIZE,
+            y * self.tile_size / Map::TILESIZE,
+        )
+    }
+}
+
+//x and y are in 0.0..1.0 with the origin at the top left of the tile
+fn shape_covers(shape: Shape, x: f32, y: f32) -> bool {
+    let (dx, dy) = (x - 0.5, y - 0.5);
+    let outside_circle = dx * dx + dy * dy > 0.25;
+    match shape {
+        Shape::Blank => false,
+        Shape::BigCircle => !outside_circle,
+        Shape::SmallCircle => dx * dx + dy * dy <= 0.0625,
+        Shape::Diamond => dx.abs() + dy.abs() <= 0.5,
+        Shape::TriangleSE => x + y >= 1.0,
+        Shape::TriangleSW => y >= x,
+        Shape::TriangleNW => x + y <= 1.0,
+        Shape::TriangleNE => x >= y,
+        //Full tiles with one corner or side rounded off
+        Shape::RoundedSE => !(dx > 0.0 && dy > 0.0 && outside_circle),
+        Shape::RoundedSW => !(dx < 0.0 && dy > 0.0 && outside_circle),
+        Shape::RoundedNW => !(dx < 0.0 && dy < 0.0 && outside_circle),
+        Shape::RoundedNE => !(dx > 0.0 && dy < 0.0 && outside_circle),
+        Shape::RoundedS => !(dy > 0.0 && outside_circle),
+        Shape::RoundedE => !(dx > 0.0 && outside_circle),
+        Shape::RoundedN => !(dy < 0.0 && outside_circle),
+        Shape::RoundedW => !(dx < 0.0 && outside_circle),
+        //Pointing towards the named side
+        Shape::TriangleN => dx.abs() <= y / 2.0,
+        Shape::TriangleE => dy.abs() <= (1.0 - x) / 2.0,
+        Shape::TriangleS => dx.abs() <= (1.0 - y) / 2.0,
+        Shape::TriangleW => dy.abs() <= x / 2.0,
+        Shape::TriangleNS => dx.abs() <= dy.abs(),
+        Shape::TriangleWE => dy.abs() <= dx.abs(),
+        Shape::HalfW => x < 0.5,
+        Shape::HalfS => y >= 0.5,
+        Shape::QuaterNE => x >= 0.5 && y < 0.5,
+        Shape::QuaterSE => x >= 0.5 && y >= 0.5,
+        Shape::QuaterSW => x < 0.5 && y >= 0.5,
+        Shape::QuaterNW => x < 0.5 && y < 0.5,
+    }
+}
+
+fn special_covers(special: Special, x: f32, y: f32) -> bool {
+    match special {
+        Special::MoveableBlock | Special::MoveableBlock2 | Special::SunkMoveableBlock => true,
+        Special::Mine | Special::BlownMine => shape_covers(Shape::SmallCircle, x, y),
+        _ => shape_covers(Shape::BigCircle, x, y),
+    }
 }
 
 impl GameMap {
+    pub const BIG_MINE_IMPULSE: f32 = Special::BIG_MINE_IMPULSE;
+    //Blast radii in pixels between tile centers, a big mine reaches the 8 tiles around it
+    pub const BIG_MINE_RADIUS: usize = 22;
     pub const HEIGHT: usize = Map::HEIGHT * Map::TILESIZE;
+    pub const MINE_IMPULSE: f32 = Special::MINE_IMPULSE;
+    pub const MINE_RADIUS: usize = 7;
     pub const WIDTH: usize = Map::WIDTH * Map::TILESIZE;
 
     /*
@@ -88,10 +338,28 @@ impl GameMap {
     }*/
 
     fn maptile_from_tile(tile: &Tile, assets: &Assets, x: usize, y: usize) -> GameMapTile {
+        GameMap::resolve_tile(tile, assets, |asset, i| asset.sample(i, x, y))
+    }
+
+    //covered tells if sprite i of the asset covers the point being resolved
+    pub(crate) fn resolve_tile(
+        tile: &Tile,
+        assets: &Assets,
+        covered: impl Fn(&Asset, usize) -> bool,
+    ) -> GameMapTile {
         match tile.special {
             None => {
-                let i = tile.shape.unwrap() as usize;
-                let shape = assets.shapes.get_bool_xy(i, x, y);
+                //Blank and uniform sprites don't need to be sampled per pixel
+                let shape = match tile.shape.unwrap() {
+                    Shape::Blank => false,
+                    shape => {
+                        let i = shape.sprite_index();
+                        assets
+                            .shapes
+                            .coverage(i)
+                            .unwrap_or_else(|| covered(&assets.shapes, i))
+                    }
+                };
                 match shape {
                     true => GameMapTile::Element(tile.foreground),
                     false => GameMapTile::Element(tile.background),
@@ -124,8 +392,8 @@ impl GameMap {
 
                     //In all other cases use shape
                     _ => {
-                        let i = tile.special.unwrap() as usize;
-                        match assets.specials.get_bool_xy(i, x, y) {
+                        let i = special.sprite_index();
+                        match covered(&assets.specials, i) {
                             true => GameMapTile::Special(special),
                             false => GameMapTile::Element(tile.background),
                         }
@@ -136,27 +404,1109 @@ impl GameMap {
     }
 
     pub fn from_map(map: &Map, assets: &Assets) -> Self {
-        let mut tiles = Vec::new();
+        let mut game_map = Self {
+            tiles: Vec::with_capacity(GameMap::WIDTH * GameMap::HEIGHT),
+            teleports: HashMap::new(),
+            illusions: HashSet::new(),
+            holes: Vec::new(),
+        };
+        game_map.update_from_map(map, assets);
+        game_map
+    }
+
+    //Same as from_map but refills the existing tiles instead of allocating.
+    //A resized map is cropped to the standard size and padded with default tiles.
+    pub fn update_from_map(&mut self, map: &Map, assets: &Assets) {
+        self.rebuild_teleports(map);
+        self.holes = map.find_all_special(Special::Hole);
+        self.holes.retain(|&(x, y)| Map::in_bounds(x, y));
+        self.tiles.clear();
+        //Look up each map tile once per row of tiles instead of once per pixel
+        for tile_y in 0..Map::HEIGHT {
+            let row: Vec<Tile> = (0..Map::WIDTH)
+                .map(|tile_x| map.get_tile(tile_x, tile_y).unwrap_or_default())
+                .collect();
+            for y in 0..Map::TILESIZE {
+                for tile in &row {
+                    for x in 0..Map::TILESIZE {
+                        self.tiles
+                            .push(GameMap::maptile_from_tile(tile, assets, x, y));
+                    }
+                }
+            }
+        }
+    }
+
+    //Recomputes only the pixels of one map tile after it changed
+    pub fn patch_tile(&mut self, map: &Map, assets: &Assets, tile_x: usize, tile_y: usize) {
+        let tile = match map.get_tile(tile_x, tile_y) {
+            Some(tile) if Map::in_bounds(tile_x, tile_y) => tile,
+            _ => return,
+        };
+        if self.tiles.len() != GameMap::WIDTH * GameMap::HEIGHT {
+            self.update_from_map(map, assets);
+            return;
+        }
+        if tile
+            .special
+            .is_some_and(|special| special.team_color().is_some())
+            || self.teleports.contains_key(&(tile_x, tile_y))
+            || self
+                .teleports
+                .values()
+                .any(|&exit| exit == (tile_x, tile_y))
+        {
+            self.rebuild_teleports(map);
+        }
+        self.holes.retain(|&hole| hole != (tile_x, tile_y));
+        if tile.special == Some(Special::Hole) {
+            self.holes.push((tile_x, tile_y));
+        }
+        for y in 0..Map::TILESIZE {
+            for x in 0..Map::TILESIZE {
+                let index =
+                    (tile_y * Map::TILESIZE + y) * GameMap::WIDTH + tile_x * Map::TILESIZE + x;
+                self.tiles[index] = GameMap::maptile_from_tile(&tile, assets, x, y);
+            }
+        }
+    }
+
+    //Moves the moveable block on map tile `tile` one tile in direction, updating
+    //both the map and the pixels. Blocks only move onto tiles without a special
+    //or anything solid. None if there is no moveable block on the tile.
+    pub fn try_push_block(
+        &mut self,
+        map: &mut Map,
+        assets: &Assets,
+        tile: (usize, usize),
+        direction: Direction4,
+    ) -> Option<PushResult> {
+        let source = map.get_tile(tile.0, tile.1)?;
+        let block = source.special.filter(|special| {
+            matches!(special, Special::MoveableBlock | Special::MoveableBlock2)
+        })?;
+
+        let offset = direction.to_vector();
+        let target_xy = (
+            tile.0.checked_add_signed(offset.x as isize),
+            tile.1.checked_add_signed(offset.y as isize),
+        );
+        let target = match target_xy {
+            (Some(x), Some(y)) => map.get_tile(x, y).map(|target| (x, y, target)),
+            _ => None,
+        };
+        let (x, y, target) = match target {
+            Some((x, y, target))
+                if target.special.is_none()
+                    && !target.background.is_solid()
+                    && (target.shape == Some(Shape::Blank) || !target.foreground.is_solid()) =>
+            {
+                (x, y, target)
+            }
+            _ => return Some(PushResult::Blocked),
+        };
+
+        map.set_tile(x, y, target.with_special(block)).ok()?;
+        map.set_tile(tile.0, tile.1, source.with_shape(Shape::Blank))
+            .ok()?;
+        self.patch_tile(map, assets, x, y);
+        self.patch_tile(map, assets, tile.0, tile.1);
+        Some(PushResult::Moved)
+    }
+
+    //Each teleport start leads to the first exit of its color
+    pub fn rebuild_teleports(&mut self, map: &Map) {
+        self.teleports.clear();
+        for &start in Special::all().iter().filter(|s| s.is_teleport_start()) {
+            if let Some(&exit) = map.find_teleport_exits(start).first() {
+                for tile in map.find_all_special(start) {
+                    self.teleports.insert(tile, exit);
+                }
+            }
+        }
+    }
+
+    //Center pixel of the closest hole and the distance to it
+    pub fn nearest_hole(&self, pos: Vector2D<f32>) -> Option<(Vector2D<f32>, f32)> {
+        self.holes
+            .iter()
+            .map(|&(x, y)| {
+                let half = Map::TILESIZE as f32 / 2.0;
+                let center = Vector2D::new(
+                    (x * Map::TILESIZE) as f32 + half,
+                    (y * Map::TILESIZE) as f32 + half,
+                );
+                (center, (center - pos).length())
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+    }
+
+    pub fn teleport_exit(&self, tile: (usize, usize)) -> Option<(usize, usize)> {
+        self.teleports.get(&tile).copied()
+    }
+
+    //Ads cover the map in the client, so optionally treat their area as a wall
+    pub fn from_map_with_ads(map: &Map, assets: &Assets, solid_ads: bool) -> Self {
+        let mut game_map = GameMap::from_map(map, assets);
+        if solid_ads {
+            for ad in &map.ads {
+                let (left, top, right, bottom) = ad.footprint();
+                let tile_x = left.clamp(0, Map::WIDTH as i32) as usize
+                    ..right.clamp(0, Map::WIDTH as i32) as usize;
+                let tile_y = top.clamp(0, Map::HEIGHT as i32) as usize
+                    ..bottom.clamp(0, Map::HEIGHT as i32) as usize;
+                for y in tile_y.start * Map::TILESIZE..tile_y.end * Map::TILESIZE {
+                    for x in tile_x.start * Map::TILESIZE..tile_x.end * Map::TILESIZE {
+                        game_map.tiles[y * GameMap::WIDTH + x] =
+                            GameMapTile::Element(Element::Block);
+                    }
+                }
+            }
+        }
+        game_map
+    }
+
+    //With illusion_wall_shadows on, fake blocks are recorded in illusions so
+    //they can be drawn like real walls
+    pub fn from_map_with_settings(map: &Map, assets: &Assets, settings: &Settings) -> Self {
+        let mut game_map = GameMap::from_map(map, assets);
+        if settings.illusion_wall_shadows {
+            game_map.illusions = map
+                .iter_tiles()
+                .filter(|(tile, _, _)| {
+                    tile.background.is_illusion()
+                        || (tile.shape.is_some_and(|shape| shape != Shape::Blank)
+                            && tile.foreground.is_illusion())
+                })
+                .map(|(_, x, y)| (x, y))
+                .collect();
+        }
+        game_map
+    }
+
+    pub fn is_illusion(&self, tile_x: usize, tile_y: usize) -> bool {
+        self.illusions.contains(&(tile_x, tile_y))
+    }
+
+    //Outside the map behaves like the block wall around it
+    pub fn friction_at(&self, x: usize, y: usize) -> f32 {
+        self.get_tile(x, y)
+            .map_or(Element::Block.get_friction(), GameMapTile::get_friction)
+    }
+
+    //Blends the friction of the four pixels around a sub-pixel position, weighted
+    //by distance to their centers. Specials and solids keep their own friction:
+    //on one the value isn't blended, next to one it isn't blended in.
+    pub fn friction_at_f32(&self, pos: Vector2D<f32>) -> f32 {
+        let wall = Element::Block.get_friction();
+        if pos.x < 0.0 || pos.y < 0.0 {
+            return wall;
+        }
+        let own = match self.get_tile(pos.x as usize, pos.y as usize) {
+            Some(tile) => tile,
+            None => return wall,
+        };
+        if own.is_special() || own.is_solid() {
+            return own.get_friction();
+        }
+
+        let (fx, fy) = (pos.x - 0.5, pos.y - 0.5);
+        let (left, top) = (fx.floor(), fy.floor());
+        let (tx, ty) = (fx - left, fy - top);
+        let mut total = 0.0;
+        let mut weight = 0.0;
+        for (dy, wy) in [(0, 1.0 - ty), (1, ty)] {
+            for (dx, wx) in [(0, 1.0 - tx), (1, tx)] {
+                let tile = self
+                    .get_tile_signed(left as i32 + dx, top as i32 + dy)
+                    .filter(|tile| tile.is_element() && !tile.is_solid());
+                if let Some(tile) = tile {
+                    total += tile.get_friction() * wx * wy;
+                    weight += wx * wy;
+                }
+            }
+        }
+        if weight > 0.0 {
+            total / weight
+        } else {
+            own.get_friction()
+        }
+    }
+
+    //Acceleration from a speed element under the pixel, zero everywhere else
+    pub fn downhill_at(&self, x: usize, y: usize) -> Vector2D<f32> {
+        self.get_tile(x, y)
+            .and_then(GameMapTile::as_element)
+            .map_or(Vector2D::zero(), |element| element.get_downhill_speed())
+    }
+
+    //Everything outside the map counts as solid
+    pub fn is_solid_at(&self, x: usize, y: usize) -> bool {
+        self.get_tile(x, y).is_none_or(GameMapTile::is_solid)
+    }
+
+    //Number of open pixels 4-connected to the start pixel, zero if it is solid.
+    //Teleports are not followed.
+    pub fn reachable_tiles(&self, start: (usize, usize)) -> usize {
+        if self.is_solid_at(start.0, start.1) {
+            return 0;
+        }
+        let mut visited = vec![false; GameMap::WIDTH * GameMap::HEIGHT];
+        let mut queue = VecDeque::from([start]);
+        visited[start.1 * GameMap::WIDTH + start.0] = true;
+        let mut count = 0;
+
+        while let Some((x, y)) = queue.pop_front() {
+            count += 1;
+            for direction in Direction4::all() {
+                let offset = direction.to_vector();
+                let (Some(nx), Some(ny)) = (
+                    x.checked_add_signed(offset.x as isize),
+                    y.checked_add_signed(offset.y as isize),
+                ) else {
+                    continue;
+                };
+                if !self.is_solid_at(nx, ny) && !visited[ny * GameMap::WIDTH + nx] {
+                    visited[ny * GameMap::WIDTH + nx] = true;
+                    queue.push_back((nx, ny));
+                }
+            }
+        }
+        count
+    }
+
+    //Every pixel with its x and y, row by row
+    pub fn iter(&self) -> Array2DIterator<'_, GameMapTile> {
+        Array2DIterator::new(&self.tiles, GameMap::WIDTH)
+    }
+
+    pub fn get_tile(&self, x: usize, y: usize) -> Option<&GameMapTile> {
+        if x < GameMap::WIDTH && y < GameMap::HEIGHT {
+            Some(&self.tiles[y * GameMap::WIDTH + x])
+        } else {
+            None
+        }
+    }
+
+    //For physics code that steps around the ball and may go negative
+    pub fn get_tile_signed(&self, x: i32, y: i32) -> Option<&GameMapTile> {
+        let x = usize::try_from(x).ok()?;
+        let y = usize::try_from(y).ok()?;
+        self.get_tile(x, y)
+    }
+
+    //Blows up the mine under pixel (x, y) and returns the impulse for the ball.
+    //The map tile becomes the blown mine, so rebuilding from the map keeps it.
+    //Live mines in the blast radius go off too.
+    pub fn trigger_mine(
+        &mut self,
+        map: &mut Map,
+        assets: &Assets,
+        x: usize,
+        y: usize,
+    ) -> Option<Vector2D<f32>> {
+        let mine = self.get_tile(x, y)?.as_special()?;
+        if !matches!(mine, Special::Mine | Special::BigMine) {
+            return None;
+        }
+        let (tile_x, tile_y) = (x / Map::TILESIZE, y / Map::TILESIZE);
+        if map.get_tile(tile_x, tile_y)?.special != Some(mine) {
+            return None;
+        }
+        self.blow_up(map, assets, (tile_x, tile_y));
+
+        let center_x = tile_x * Map::TILESIZE + Map::TILESIZE / 2;
+        let center_y = tile_y * Map::TILESIZE + Map::TILESIZE / 2;
+        let direction = Vector2D::new(x as f32 - center_x as f32, y as f32 - center_y as f32);
+        mine.impulse_on_hit(direction)
+    }
+
+    //Turns the mine on map tile `tile` into its blown variant, then does the same
+    //for every live mine whose center is within its blast radius
+    fn blow_up(&mut self, map: &mut Map, assets: &Assets, tile: (usize, usize)) {
+        let mut pending = vec![tile];
+        while let Some((tile_x, tile_y)) = pending.pop() {
+            let Some(tile) = map.get_tile_mut(tile_x, tile_y) else {
+                continue;
+            };
+            let (blown, radius) = match tile.special {
+                Some(Special::Mine) => (Special::BlownMine, GameMap::MINE_RADIUS),
+                Some(Special::BigMine) => (Special::BlownBigMine, GameMap::BIG_MINE_RADIUS),
+                _ => continue,
+            };
+            tile.special = Some(blown);
+            self.patch_tile(map, assets, tile_x, tile_y);
+
+            let reach = radius / Map::TILESIZE;
+            for y in tile_y.saturating_sub(reach)..=tile_y + reach {
+                for x in tile_x.saturating_sub(reach)..=tile_x + reach {
+                    let delta_x = x.abs_diff(tile_x) * Map::TILESIZE;
+                    let delta_y = y.abs_diff(tile_y) * Map::TILESIZE;
+                    if delta_x * delta_x + delta_y * delta_y <= radius * radius {
+                        pending.push((x, y));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::array2diter::Array2DRangeIterator;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn grass_map() -> GameMap {
+        GameMap {
+            tiles: vec![GameMapTile::Element(Element::Grass); GameMap::WIDTH * GameMap::HEIGHT],
+            teleports: HashMap::new(),
+            illusions: HashSet::new(),
+            holes: Vec::new(),
+        }
+    }
+
+    fn place(map: &mut GameMap, tile_x: usize, tile_y: usize, tile: GameMapTile) {
+        for y in 0..Map::TILESIZE {
+            for x in 0..Map::TILESIZE {
+                let index =
+                    (tile_y * Map::TILESIZE + y) * GameMap::WIDTH + tile_x * Map::TILESIZE + x;
+                map.tiles[index] = tile;
+            }
+        }
+    }
+
+    fn blank_assets() -> Assets {
+        let blank = || {
+            Asset::from_sprites(
+                vec![vec![false; Map::TILESIZE * Map::TILESIZE]; 28],
+                Map::TILESIZE,
+            )
+        };
+        Assets {
+            specials: blank(),
+            shapes: blank(),
+        }
+    }
+
+    //Every sprite covers the left half of its tile
+    fn half_assets() -> Assets {
+        let sprite: Vec<bool> = (0..Map::TILESIZE * Map::TILESIZE)
+            .map(|i| i % Map::TILESIZE < Map::TILESIZE / 2)
+            .collect();
+        let half = || Asset::from_sprites(vec![sprite.clone(); 28], Map::TILESIZE);
+        Assets {
+            specials: half(),
+            shapes: half(),
+        }
+    }
+
+    fn resolved_friction(tile: &Tile, assets: &Assets, x: usize) -> f32 {
+        GameMap::maptile_from_tile(tile, assets, x, 7).get_friction()
+    }
+
+    #[test]
+    fn test_friction_precedence() {
+        let assets = half_assets();
+        let on = |special, background| Tile::new(Some(special), None, background, Element::Grass);
+
+        //Inside the sprite the special wins, outside it the background does
+        let sunk = on(Special::SunkMoveableBlock, Element::Ice);
+        assert_eq!(resolved_friction(&sunk, &assets, 0), 0.9935);
+        assert_eq!(resolved_friction(&sunk, &assets, 14), 0.9975);
+
+        //These always use the background, even inside the sprite
+        for special in [
+            Special::FakeHole,
+            Special::BlueTeleportExit,
+            Special::RedTeleportExit,
+            Special::YellowTeleportExit,
+            Special::GreenTeleportExit,
+            Special::MagnetRepel,
+        ] {
+            let tile = on(special, Element::Mud);
+            assert_eq!(resolved_friction(&tile, &assets, 0), 0.8);
+            assert_eq!(resolved_friction(&tile, &assets, 14), 0.8);
+        }
+
+        //Attracting magnets cover the whole tile unless they sit in liquid
+        let magnet = on(Special::MagnetAttract, Element::Ice);
+        assert_eq!(resolved_friction(&magnet, &assets, 14), 0.9);
+        let magnet = on(Special::MagnetAttract, Element::WaterSwamp);
+        assert_eq!(resolved_friction(&magnet, &assets, 0), 0.95);
+
+        //Shapes pick between foreground and background
+        let shape = Tile::new(None, Some(Shape::BigCircle), Element::Ice, Element::Mud);
+        assert_eq!(resolved_friction(&shape, &assets, 0), 0.8);
+        assert_eq!(resolved_friction(&shape, &assets, 14), 0.9975);
+    }
+
+    #[test]
+    fn test_shared_in() {
+        static LOCK: OnceLock<Assets> = OnceLock::new();
+        let mut loads = 0;
+        let first = Assets::shared_in(&LOCK, || {
+            loads += 1;
+            Ok(blank_assets())
+        })
+        .unwrap();
+        let second = Assets::shared_in(&LOCK, || {
+            loads += 1;
+            Ok(blank_assets())
+        })
+        .unwrap();
+
+        assert!(std::ptr::eq(first, second));
+        assert_eq!(loads, 1);
+    }
+
+    #[test]
+    #[cfg(feature = "image")]
+    #[ignore = "needs the sprite sheets at Assets::SPECIALPATH and Assets::SHAPEPATH"]
+    fn test_shared() {
+        let first = Assets::shared().unwrap();
+        let second = Assets::shared().unwrap();
+        assert!(std::ptr::eq(first, second));
+    }
+
+    #[test]
+    fn test_shared_in_retries_failed_load() {
+        static LOCK: OnceLock<Assets> = OnceLock::new();
+        let failed = Assets::shared_in(&LOCK, || Err(u8::try_from(256i32).unwrap_err().into()));
+        assert!(failed.is_err());
+        assert!(Assets::shared_in(&LOCK, || Ok(blank_assets())).is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "image")]
+    fn test_load_with_size() {
+        //Two 8x8 sprites: the first fully opaque, the second only in its top left quarter
+        let mut image = image::RgbaImage::new(16, 8);
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            let opaque = x < 8 || (x < 12 && y < 4);
+            *pixel = image::Rgba([0, 0, 0, if opaque { 255 } else { 0 }]);
+        }
+        let file = tempfile::Builder::new().suffix(".png").tempfile().unwrap();
+        image.save(file.path()).unwrap();
+
+        let asset = Asset::load_with_size(file.path().to_str().unwrap(), 2, 8).unwrap();
+
+        assert_eq!(asset.tile_size(), 8);
+        assert_eq!(asset.get(0).unwrap().len(), 64);
+        assert!(asset.get_bool_xy(0, 7, 7));
+        assert!(asset.get_bool_xy(1, 3, 3));
+        assert!(!asset.get_bool_xy(1, 4, 3));
+        assert!(!asset.get_bool_xy(1, 3, 4));
+
+        //Sampling maps the 15px tile onto the 8px sprite
+        assert!(asset.sample(1, 7, 7));
+        assert!(!asset.sample(1, 8, 0));
+        assert!(!asset.sample(1, 14, 14));
+    }
+
+    #[test]
+    #[cfg(feature = "image")]
+    fn test_load_from_bytes() {
+        let mut image = image::RgbaImage::new(30, 15);
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            let opaque = (x + y) % 3 == 0;
+            *pixel = image::Rgba([0, 0, 0, if opaque { 255 } else { 0 }]);
+        }
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        image
+            .write_to(&mut bytes, image::ImageOutputFormat::Png)
+            .unwrap();
+        let file = tempfile::Builder::new().suffix(".png").tempfile().unwrap();
+        std::fs::write(file.path(), bytes.get_ref()).unwrap();
+
+        let from_file = Asset::load(file.path().to_str().unwrap(), 2).unwrap();
+        let from_bytes = Asset::load_from_bytes(bytes.get_ref(), 2, Map::TILESIZE).unwrap();
+        assert_eq!(from_file, from_bytes);
+
+        let other = Asset::load_from_bytes(bytes.get_ref(), 1, Map::TILESIZE).unwrap();
+        assert_ne!(from_file, other);
+        assert!(Asset::load_from_bytes(b"not a png", 1, Map::TILESIZE).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "image")]
+    fn test_asset_dimension_mismatch() {
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::new(20, 15));
+        match Asset::from_image(&image, 28, Map::TILESIZE) {
+            Err(AssetError::DimensionMismatch { expected, got }) => {
+                assert_eq!(expected, (420, 15));
+                assert_eq!(got, (20, 15));
+            }
+            other => panic!("expected DimensionMismatch, got {:?}", other.err()),
+        }
+
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::new(420, 14));
+        assert!(matches!(
+            Asset::from_image(&image, 28, Map::TILESIZE),
+            Err(AssetError::DimensionMismatch { .. })
+        ));
+
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::new(420, 15));
+        assert!(Asset::from_image(&image, 28, Map::TILESIZE).is_ok());
+    }
+
+    #[test]
+    fn test_procedural_assets() {
+        let assets = Assets::procedural(Map::TILESIZE);
+        assert_eq!(
+            assets.shapes.coverage(Shape::Blank.sprite_index()),
+            Some(false)
+        );
+        assert_eq!(
+            assets.shapes.coverage(Shape::BigCircle.sprite_index()),
+            None
+        );
+        assert!(assets.shapes.sample(Shape::BigCircle.sprite_index(), 7, 7));
+        assert!(!assets.shapes.sample(Shape::BigCircle.sprite_index(), 0, 0));
+        assert!(assets
+            .shapes
+            .sample(Shape::TriangleSE.sprite_index(), 14, 14));
+        assert!(!assets.shapes.sample(Shape::TriangleSE.sprite_index(), 0, 0));
+        assert!(assets.shapes.sample(Shape::HalfW.sprite_index(), 0, 14));
+        assert!(!assets.shapes.sample(Shape::HalfW.sprite_index(), 14, 0));
+
+        let mut map = Map::new();
+        let hole = Tile::new(Some(Special::Hole), None, Element::Grass, Element::Grass);
+        map.set_tile(2, 3, hole).unwrap();
+        let shape = Tile::new(None, Some(Shape::QuaterNW), Element::Ice, Element::Block);
+        map.set_tile(4, 3, shape).unwrap();
+        let game_map = GameMap::from_map(&map, &assets);
+
+        let (x, y) = (2 * Map::TILESIZE, 3 * Map::TILESIZE);
+        assert_eq!(
+            game_map.get_tile(x + 7, y + 7),
+            Some(&GameMapTile::Special(Special::Hole))
+        );
+        assert_eq!(
+            game_map.get_tile(x, y),
+            Some(&GameMapTile::Element(Element::Grass))
+        );
+        let x = 4 * Map::TILESIZE;
+        assert_eq!(
+            game_map.get_tile(x + 2, y + 2),
+            Some(&GameMapTile::Element(Element::Block))
+        );
+        assert_eq!(
+            game_map.get_tile(x + 12, y + 12),
+            Some(&GameMapTile::Element(Element::Ice))
+        );
+    }
+
+    #[test]
+    fn test_from_map_with_ads() {
+        let mut map = Map::new();
+        map.ads = crate::map::Ad::from_string("A1005").unwrap();
+        let assets = blank_assets();
+
+        let game_map = GameMap::from_map_with_ads(&map, &assets, false);
+        assert!(!game_map.is_solid_at(11 * Map::TILESIZE, 6 * Map::TILESIZE));
+
+        let game_map = GameMap::from_map_with_ads(&map, &assets, true);
+        //Small ads cover 3x2 tiles starting at (10, 5)
+        assert!(game_map.is_solid_at(10 * Map::TILESIZE, 5 * Map::TILESIZE));
+        assert!(game_map.is_solid_at(13 * Map::TILESIZE - 1, 7 * Map::TILESIZE - 1));
+        assert!(!game_map.is_solid_at(13 * Map::TILESIZE, 5 * Map::TILESIZE));
+        assert!(!game_map.is_solid_at(10 * Map::TILESIZE, 7 * Map::TILESIZE));
+        assert!(!game_map.is_solid_at(10 * Map::TILESIZE - 1, 5 * Map::TILESIZE));
+    }
+
+    #[test]
+    fn test_accessors() {
+        let element = GameMapTile::Element(Element::Mud);
+        assert_eq!(element.as_element(), Some(Element::Mud));
+        assert_eq!(element.as_special(), None);
+        assert!(element.is_element());
+        assert!(!element.is_special());
+        assert!(!element.is_solid());
+
+        let special = GameMapTile::Special(Special::MoveableBlock);
+        assert_eq!(special.as_element(), None);
+        assert_eq!(special.as_special(), Some(Special::MoveableBlock));
+        assert!(!special.is_element());
+        assert!(special.is_special());
+        assert!(special.is_solid());
+        assert!(GameMapTile::Element(Element::Block).is_solid());
+    }
+
+    #[test]
+    fn test_friction_at_f32() {
+        let mut map = grass_map();
+        let grass = Element::Grass.get_friction();
+        let mud = Element::Mud.get_friction();
+        map.tiles[10 * GameMap::WIDTH + 10] = GameMapTile::Element(Element::Mud);
+
+        //Pixel centers give the pixel's own friction
+        assert_eq!(map.friction_at_f32(Vector2D::new(10.5, 10.5)), mud);
+        assert_eq!(map.friction_at_f32(Vector2D::new(11.5, 10.5)), grass);
+
+        let between = map.friction_at_f32(Vector2D::new(11.0, 10.5));
+        assert!(between > grass.min(mud) && between < grass.max(mud));
+        assert!((between - (grass + mud) / 2.0).abs() < 0.0001);
+
+        //Holes and blocks are never blended
+        place(&mut map, 2, 2, GameMapTile::Special(Special::Hole));
+        place(&mut map, 4, 2, GameMapTile::Element(Element::Block));
+        assert_eq!(
+            map.friction_at_f32(Vector2D::new(30.2, 30.5)),
+            Special::Hole.get_friction()
+        );
+        assert!((map.friction_at_f32(Vector2D::new(29.8, 30.5)) - grass).abs() < 0.0001);
+        assert!((map.friction_at_f32(Vector2D::new(59.8, 30.5)) - grass).abs() < 0.0001);
+        assert_eq!(
+            map.friction_at_f32(Vector2D::new(-1.0, 30.5)),
+            Element::Block.get_friction()
+        );
+    }
+
+    #[test]
+    fn test_try_push_block() {
+        let assets = Assets::procedural(Map::TILESIZE);
+        let mut map = Map::new();
+        let block = Tile::default().with_special(Special::MoveableBlock);
+        map.set_tile(5, 5, block).unwrap();
+        map.set_tile(5, 3, Tile::default().with_background(Element::Block))
+            .unwrap();
+        let mut game_map = GameMap::from_map(&map, &assets);
+
+        let result = game_map.try_push_block(&mut map, &assets, (5, 5), Direction4::E);
+        assert_eq!(result, Some(PushResult::Moved));
+        assert_eq!(
+            map.get_tile(6, 5).unwrap().special,
+            Some(Special::MoveableBlock)
+        );
+        assert_eq!(map.get_tile(5, 5).unwrap().special, None);
+        assert!(game_map.is_solid_at(6 * Map::TILESIZE + 7, 5 * Map::TILESIZE + 7));
+        assert!(!game_map.is_solid_at(5 * Map::TILESIZE + 7, 5 * Map::TILESIZE + 7));
+
+        //Into a wall, off the map and not a block at all
+        map.set_tile(6, 4, block).unwrap();
+        map.set_tile(6, 0, block).unwrap();
+        let result = game_map.try_push_block(&mut map, &assets, (6, 5), Direction4::N);
+        assert_eq!(result, Some(PushResult::Blocked));
+        let result = game_map.try_push_block(&mut map, &assets, (6, 0), Direction4::N);
+        assert_eq!(result, Some(PushResult::Blocked));
+        assert_eq!(map.get_tile(6, 0), Some(block));
+        let result = game_map.try_push_block(&mut map, &assets, (1, 1), Direction4::N);
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn test_nearest_hole() {
+        let assets = Assets::procedural(Map::TILESIZE);
+        let mut map = Map::new();
+        let hole = Tile::new(Some(Special::Hole), None, Element::Grass, Element::Grass);
+        map.set_tile(5, 5, hole).unwrap();
+        map.set_tile(40, 20, hole).unwrap();
+        let mut game_map = GameMap::from_map(&map, &assets);
+
+        let (center, distance) = game_map.nearest_hole(Vector2D::new(82.5, 97.5)).unwrap();
+        assert!(center.approx_eq(&Vector2D::new(82.5, 82.5), 1e-6));
+        assert!((distance - 15.0).abs() < 1e-4);
+        let (center, _) = game_map.nearest_hole(Vector2D::new(600.0, 300.0)).unwrap();
+        assert!(center.approx_eq(&Vector2D::new(607.5, 307.5), 1e-6));
+
+        map.set_tile(40, 20, Tile::default()).unwrap();
+        game_map.patch_tile(&map, &assets, 40, 20);
+        let (center, _) = game_map.nearest_hole(Vector2D::new(600.0, 300.0)).unwrap();
+        assert!(center.approx_eq(&Vector2D::new(82.5, 82.5), 1e-6));
+
+        assert!(GameMap::from_map(&Map::new(), &assets)
+            .nearest_hole(Vector2D::zero())
+            .is_none());
+    }
+
+    #[test]
+    fn test_special_collision() {
+        let mut map = grass_map();
+        for (x, special) in [
+            Special::MoveableBlock,
+            Special::SunkMoveableBlock,
+            Special::FullBreakable,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            map.tiles[x] = GameMapTile::Special(special);
+        }
+        assert!(map.is_solid_at(0, 0));
+        assert!(!map.is_solid_at(1, 0));
+        assert!(map.is_solid_at(2, 0));
+    }
+
+    #[test]
+    fn test_reachable_tiles() {
+        let assets = Assets::procedural(Map::TILESIZE);
+        let mut map = Map::new();
+        let block = Tile::default().with_background(Element::Block);
+        for y in 0..Map::HEIGHT {
+            map.set_tile(10, y, block).unwrap();
+        }
+        let game_map = GameMap::from_map(&map, &assets);
+
+        let left = 10 * Map::TILESIZE * GameMap::HEIGHT;
+        let right = (Map::WIDTH - 11) * Map::TILESIZE * GameMap::HEIGHT;
+        assert_eq!(game_map.reachable_tiles((0, 0)), left);
+        assert_eq!(game_map.reachable_tiles((GameMap::WIDTH - 1, 5)), right);
+        assert_eq!(game_map.reachable_tiles((10 * Map::TILESIZE, 0)), 0);
+        assert_eq!(game_map.reachable_tiles((GameMap::WIDTH, 0)), 0);
+    }
+
+    #[test]
+    fn test_illusion_wall_shadows() {
+        let assets = Assets::procedural(Map::TILESIZE);
+        let mut map = Map::new();
+        let fake = Tile::default().with_background(Element::FakeBlock);
+        map.set_tile(4, 4, fake).unwrap();
+        let half = Tile::new(None, Some(Shape::HalfW), Element::Grass, Element::FakeBlock);
+        map.set_tile(6, 4, half).unwrap();
+        map.set_tile(8, 4, Tile::default().with_foreground(Element::FakeBlock))
+            .unwrap();
+
+        let mut settings: Settings = "fttt14".parse().unwrap();
+        let game_map = GameMap::from_map_with_settings(&map, &assets, &settings);
+        assert!(game_map.is_illusion(4, 4));
+        assert!(game_map.is_illusion(6, 4));
+        assert!(!game_map.is_illusion(8, 4));
+        assert_eq!(game_map.illusions.len(), 2);
+        assert!(!game_map.is_solid_at(4 * Map::TILESIZE + 7, 4 * Map::TILESIZE + 7));
+        assert!(!game_map.is_solid_at(6 * Map::TILESIZE + 2, 4 * Map::TILESIZE + 7));
+
+        settings.illusion_wall_shadows = false;
+        let game_map = GameMap::from_map_with_settings(&map, &assets, &settings);
+        assert!(game_map.illusions.is_empty());
+    }
+
+    #[test]
+    fn test_downhill_at() {
+        let mut map = grass_map();
+        place(&mut map, 1, 0, GameMapTile::Element(Element::SpeedNE));
+        place(&mut map, 2, 0, GameMapTile::Special(Special::Hole));
+
+        let slope = map.downhill_at(22, 7);
+        let expected = Element::SpeedNE.get_downhill_speed();
+        assert_eq!((slope.x, slope.y), (expected.x, expected.y));
+        assert!(slope.x > 0.0 && slope.y < 0.0);
+
+        for (x, y) in [(7, 7), (37, 7), (GameMap::WIDTH, 0)] {
+            let flat = map.downhill_at(x, y);
+            assert_eq!((flat.x, flat.y), (0.0, 0.0));
+        }
+    }
+
+    #[test]
+    fn test_friction_at() {
+        let mut map = grass_map();
+        place(
+            &mut map,
+            0,
+            0,
+            GameMapTile::Special(Special::BlueTeleportStart),
+        );
+        place(&mut map, 1, 0, GameMapTile::Element(Element::Mud));
+
+        assert_eq!(map.friction_at(7, 7), 0.9975);
+        assert_eq!(map.friction_at(22, 7), 0.8);
+        assert_eq!(map.friction_at(37, 7), Element::Grass.get_friction());
+        assert_eq!(
+            map.friction_at(GameMap::WIDTH, 0),
+            Element::Block.get_friction()
+        );
+    }
+
+    #[test]
+    fn test_from_map_matches_per_pixel() {
+        let assets = half_assets();
+        let map = Map::random(&mut StdRng::seed_from_u64(5));
+
+        //The original lookup, one get_tile per pixel
+        let mut expected = Vec::new();
         for (_, x, y) in
             Array2DRangeIterator::<usize>::new(0..GameMap::WIDTH * GameMap::HEIGHT, GameMap::WIDTH)
         {
             if let Some(tile) = map.get_tile(x / Map::TILESIZE, y / Map::TILESIZE) {
-                tiles.push(GameMap::maptile_from_tile(
+                expected.push(GameMap::maptile_from_tile(
                     &tile,
-                    assets,
+                    &assets,
                     x % Map::TILESIZE,
                     y % Map::TILESIZE,
                 ));
             }
         }
-        Self { tiles }
+
+        assert_eq!(GameMap::from_map(&map, &assets).tiles, expected);
     }
 
-    pub fn get_tile(&self, x: usize, y: usize) -> Option<&GameMapTile> {
-        if x < GameMap::WIDTH && y < GameMap::HEIGHT {
-            Some(&self.tiles[y * GameMap::WIDTH + x])
-        } else {
-            None
+    #[test]
+    fn test_uniform_shapes() {
+        //Every sprite, including Blank's, fully covered
+        let full = || {
+            Asset::from_sprites(
+                vec![vec![true; Map::TILESIZE * Map::TILESIZE]; 28],
+                Map::TILESIZE,
+            )
+        };
+        let assets = Assets {
+            specials: full(),
+            shapes: full(),
+        };
+        assert_eq!(assets.shapes.coverage(0), Some(true));
+        assert_eq!(half_assets().shapes.coverage(0), None);
+        assert_eq!(blank_assets().shapes.coverage(0), Some(false));
+
+        let blank = Tile::new(None, Some(Shape::Blank), Element::Grass, Element::Block);
+        let circle = Tile::new(None, Some(Shape::BigCircle), Element::Grass, Element::Block);
+        for (x, y) in [(0, 0), (7, 7), (14, 14)] {
+            assert_eq!(
+                GameMap::maptile_from_tile(&blank, &assets, x, y),
+                GameMapTile::Element(Element::Grass)
+            );
+            assert_eq!(
+                GameMap::maptile_from_tile(&circle, &assets, x, y),
+                GameMapTile::Element(Element::Block)
+            );
+        }
+    }
+
+    #[test]
+    fn test_update_from_map() {
+        let assets = half_assets();
+        let mut map = Map::new();
+        let block = Tile::new(None, Some(Shape::Blank), Element::Block, Element::Grass);
+        map.set_tile(3, 4, block).unwrap();
+        let mut game_map = GameMap::from_map(&map, &assets);
+        let capacity = game_map.tiles.capacity();
+
+        let moved = Tile::new(None, Some(Shape::BigCircle), Element::Mud, Element::Ice);
+        map.set_tile(3, 4, Tile::default()).unwrap();
+        map.set_tile(4, 4, moved).unwrap();
+        game_map.update_from_map(&map, &assets);
+
+        assert_eq!(game_map.tiles, GameMap::from_map(&map, &assets).tiles);
+        assert_eq!(game_map.tiles.capacity(), capacity);
+
+        //Resized maps are cropped and padded to the standard size
+        let hole = Tile::new(Some(Special::Hole), None, Element::Grass, Element::Grass);
+        map.set_tile(3, 4, hole).unwrap();
+        let mut resized = map.clone();
+        resized.resize(60, 30);
+        resized.set_tile(55, 28, hole).unwrap();
+        game_map.update_from_map(&resized, &assets);
+        assert_eq!(game_map.tiles, GameMap::from_map(&map, &assets).tiles);
+        assert_eq!(game_map.holes, vec![(3, 4)]);
+        game_map.patch_tile(&resized, &assets, 55, 28);
+        assert_eq!(game_map.tiles.len(), GameMap::WIDTH * GameMap::HEIGHT);
+
+        resized.resize(10, 5);
+        game_map.update_from_map(&resized, &assets);
+        map.resize(10, 5);
+        map.resize(Map::WIDTH, Map::HEIGHT);
+        assert_eq!(game_map.tiles, GameMap::from_map(&map, &assets).tiles);
+    }
+
+    #[test]
+    fn test_patch_tile() {
+        let assets = half_assets();
+        let mut map = Map::new();
+        let mine = Tile::new(Some(Special::Mine), None, Element::Grass, Element::Grass);
+        map.set_tile(10, 5, mine).unwrap();
+        map.set_tile(Map::WIDTH - 1, Map::HEIGHT - 1, mine).unwrap();
+        let mut game_map = GameMap::from_map(&map, &assets);
+
+        let blown = Tile::new(Some(Special::BlownMine), None, Element::Mud, Element::Mud);
+        map.set_tile(10, 5, blown).unwrap();
+        game_map.patch_tile(&map, &assets, 10, 5);
+        assert_eq!(game_map.tiles, GameMap::from_map(&map, &assets).tiles);
+
+        let block = Tile::new(None, Some(Shape::BigCircle), Element::Block, Element::Ice);
+        map.set_tile(Map::WIDTH - 1, Map::HEIGHT - 1, block)
+            .unwrap();
+        game_map.patch_tile(&map, &assets, Map::WIDTH - 1, Map::HEIGHT - 1);
+        assert_eq!(game_map.tiles, GameMap::from_map(&map, &assets).tiles);
+
+        //Outside the map nothing changes
+        game_map.patch_tile(&map, &assets, Map::WIDTH, 0);
+        assert_eq!(game_map.tiles, GameMap::from_map(&map, &assets).tiles);
+    }
+
+    #[test]
+    fn test_teleport_table() {
+        let assets = blank_assets();
+        let teleport = |special| Tile::new(Some(special), None, Element::Grass, Element::Grass);
+        let mut map = Map::new();
+        map.set_tile(3, 3, teleport(Special::BlueTeleportStart))
+            .unwrap();
+        map.set_tile(4, 7, teleport(Special::BlueTeleportStart))
+            .unwrap();
+        map.set_tile(30, 12, teleport(Special::BlueTeleportExit))
+            .unwrap();
+        map.set_tile(10, 10, teleport(Special::RedTeleportStart))
+            .unwrap();
+
+        let mut game_map = GameMap::from_map(&map, &assets);
+        assert_eq!(game_map.teleports.len(), 2);
+        assert_eq!(game_map.teleport_exit((3, 3)), Some((30, 12)));
+        assert_eq!(game_map.teleport_exit((4, 7)), Some((30, 12)));
+        //Red has no exit
+        assert_eq!(game_map.teleport_exit((10, 10)), None);
+        assert_eq!(game_map.teleport_exit((30, 12)), None);
+
+        map.set_tile(40, 20, teleport(Special::RedTeleportExit))
+            .unwrap();
+        game_map.patch_tile(&map, &assets, 40, 20);
+        assert_eq!(game_map.teleport_exit((10, 10)), Some((40, 20)));
+    }
+
+    #[test]
+    fn test_iter() {
+        let mut map = grass_map();
+        map.tiles[1] = GameMapTile::Element(Element::Ice);
+        map.tiles[GameMap::WIDTH] = GameMapTile::Element(Element::Mud);
+
+        let first: Vec<(GameMapTile, usize, usize)> = map
+            .iter()
+            .take(3)
+            .map(|(tile, x, y)| (*tile, x, y))
+            .collect();
+        assert_eq!(
+            first,
+            vec![
+                (GameMapTile::Element(Element::Grass), 0, 0),
+                (GameMapTile::Element(Element::Ice), 1, 0),
+                (GameMapTile::Element(Element::Grass), 2, 0),
+            ]
+        );
+
+        let (tile, x, y) = map.iter().nth(GameMap::WIDTH).unwrap();
+        assert_eq!((*tile, x, y), (GameMapTile::Element(Element::Mud), 0, 1));
+        let (_, x, y) = map.iter().last().unwrap();
+        assert_eq!((x, y), (GameMap::WIDTH - 1, GameMap::HEIGHT - 1));
+        assert_eq!(map.iter().count(), GameMap::WIDTH * GameMap::HEIGHT);
+    }
+
+    #[test]
+    fn test_get_tile_signed() {
+        let mut map = grass_map();
+        place(&mut map, 0, 0, GameMapTile::Element(Element::Ice));
+        assert_eq!(
+            map.get_tile_signed(0, 0),
+            Some(&GameMapTile::Element(Element::Ice))
+        );
+        assert_eq!(
+            map.get_tile_signed(GameMap::WIDTH as i32 - 1, GameMap::HEIGHT as i32 - 1),
+            Some(&GameMapTile::Element(Element::Grass))
+        );
+        assert_eq!(map.get_tile_signed(-1, 0), None);
+        assert_eq!(map.get_tile_signed(0, -1), None);
+        assert_eq!(map.get_tile_signed(i32::MIN, i32::MIN), None);
+        assert_eq!(map.get_tile_signed(GameMap::WIDTH as i32, 0), None);
+        assert_eq!(map.get_tile_signed(0, GameMap::HEIGHT as i32), None);
+    }
+
+    fn mine_map(special: Special) -> (Map, GameMap, Assets) {
+        let assets = Assets::procedural(Map::TILESIZE);
+        let mut map = Map::new();
+        let mine = Tile::new(Some(special), None, Element::Grass, Element::Grass);
+        map.set_tile(2, 2, mine).unwrap();
+        let game_map = GameMap::from_map(&map, &assets);
+        (map, game_map, assets)
+    }
+
+    #[test]
+    fn test_trigger_mine() {
+        let (mut map, mut game_map, assets) = mine_map(Special::Mine);
+
+        let impulse = game_map.trigger_mine(&mut map, &assets, 40, 37).unwrap();
+        assert_eq!(
+            game_map.get_tile(40, 37),
+            Some(&GameMapTile::Special(Special::BlownMine))
+        );
+        assert_eq!(
+            map.get_tile(2, 2).unwrap().special,
+            Some(Special::BlownMine)
+        );
+        assert!(impulse.x > 0.0);
+        assert!(impulse.y.abs() < 0.0001);
+        assert!((impulse.length() - GameMap::MINE_IMPULSE).abs() < 0.0001);
+
+        //Blown mines don't go off twice, not even after rebuilding from the map
+        assert!(game_map.trigger_mine(&mut map, &assets, 40, 37).is_none());
+        game_map.update_from_map(&map, &assets);
+        assert_eq!(
+            game_map.get_tile(40, 37),
+            Some(&GameMapTile::Special(Special::BlownMine))
+        );
+        assert!(game_map.trigger_mine(&mut map, &assets, 40, 37).is_none());
+    }
+
+    #[test]
+    fn test_trigger_big_mine() {
+        let (mut map, mut game_map, assets) = mine_map(Special::BigMine);
+        let before = game_map.tiles.clone();
+
+        let impulse = game_map.trigger_mine(&mut map, &assets, 37, 37).unwrap();
+        assert_eq!(impulse.y, -GameMap::BIG_MINE_IMPULSE);
+        assert_eq!(
+            map.get_tile(2, 2).unwrap().special,
+            Some(Special::BlownBigMine)
+        );
+        //Only the mine's own tile changes, the grass around it stays grass
+        for (i, (old, new)) in before.iter().zip(&game_map.tiles).enumerate() {
+            let (x, y) = (i % GameMap::WIDTH, i / GameMap::WIDTH);
+            let own = x / Map::TILESIZE == 2 && y / Map::TILESIZE == 2;
+            assert!(own || old == new, "pixel ({}, {}) changed", x, y);
         }
+        assert_eq!(
+            game_map.get_tile(37, 48),
+            Some(&GameMapTile::Element(Element::Grass))
+        );
+    }
+
+    #[test]
+    fn test_mine_radius() {
+        let mine = |special| Tile::new(Some(special), None, Element::Grass, Element::Grass);
+        let special_at = |map: &Map, x, y| map.get_tile(x, y).unwrap().special;
+
+        //A small mine leaves its neighbors alone
+        let (mut map, _, assets) = mine_map(Special::Mine);
+        map.set_tile(3, 3, mine(Special::Mine)).unwrap();
+        let mut game_map = GameMap::from_map(&map, &assets);
+        game_map.trigger_mine(&mut map, &assets, 37, 37).unwrap();
+        assert_eq!(special_at(&map, 2, 2), Some(Special::BlownMine));
+        assert_eq!(special_at(&map, 3, 3), Some(Special::Mine));
+
+        //The same neighbor goes off next to a big mine, and a big neighbor
+        //reaches one tile further
+        let (mut map, _, assets) = mine_map(Special::BigMine);
+        map.set_tile(3, 3, mine(Special::BigMine)).unwrap();
+        map.set_tile(4, 3, mine(Special::Mine)).unwrap();
+        map.set_tile(6, 3, mine(Special::Mine)).unwrap();
+        let mut game_map = GameMap::from_map(&map, &assets);
+        game_map.trigger_mine(&mut map, &assets, 37, 37).unwrap();
+        assert_eq!(special_at(&map, 2, 2), Some(Special::BlownBigMine));
+        assert_eq!(special_at(&map, 3, 3), Some(Special::BlownBigMine));
+        assert_eq!(special_at(&map, 4, 3), Some(Special::BlownMine));
+        assert_eq!(special_at(&map, 6, 3), Some(Special::Mine));
+        assert_eq!(
+            game_map.tiles,
+            GameMap::from_map(&map, &assets).tiles,
+            "every blown mine is patched into the pixels"
+        );
+    }
+
+    #[test]
+    fn test_trigger_mine_on_grass() {
+        let (mut map, mut game_map, assets) = mine_map(Special::Mine);
+        assert!(game_map.trigger_mine(&mut map, &assets, 10, 10).is_none());
+        assert!(game_map
+            .trigger_mine(&mut map, &assets, GameMap::WIDTH, 0)
+            .is_none());
+        //The grass around the mine sprite is not the mine
+        assert!(game_map.trigger_mine(&mut map, &assets, 31, 31).is_none());
     }
 }
@@ -0,0 +1,237 @@
+use crate::map::{Map, MapError};
+use crate::tile::Tile;
+use std::collections::VecDeque;
+
+#[derive(Debug, Copy, Clone)]
+struct TileChange {
+    x: usize,
+    y: usize,
+    old: Tile,
+    new: Tile,
+}
+
+//Undo/redo for editor operations. Each edit stores only the tiles it changed.
+#[derive(Debug, Default)]
+pub struct MapHistory {
+    map: Map,
+    undo: Vec<Vec<TileChange>>,
+    redo: Vec<Vec<TileChange>>,
+}
+
+impl MapHistory {
+    pub fn new(map: Map) -> Self {
+        Self {
+            map,
+            undo: Vec::new(),
+            redo: Vec::new(),
+        }
+    }
+
+    pub fn map(&self) -> &Map {
+        &self.map
+    }
+
+    pub fn into_map(self) -> Map {
+        self.map
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn set_tile(&mut self, x: usize, y: usize, tile: Tile) -> Result<(), MapError> {
+        self.edit(vec![(x, y)], tile)
+    }
+
+    //Fills the rectangle between the two corners, both inclusive
+    pub fn fill_region(
+        &mut self,
+        from: (usize, usize),
+        to: (usize, usize),
+        tile: Tile,
+    ) -> Result<(), MapError> {
+        let (left, right) = (from.0.min(to.0), from.0.max(to.0));
+        let (top, bottom) = (from.1.min(to.1), from.1.max(to.1));
+        let coords = (top..=bottom)
+            .flat_map(|y| (left..=right).map(move |x| (x, y)))
+            .collect();
+        self.edit(coords, tile)
+    }
+
+    //Replaces the 4-connected area of tiles equal to the one at start
+    pub fn flood_fill(&mut self, start: (usize, usize), tile: Tile) -> Result<(), MapError> {
+        let target = self
+            .map
+            .get_tile(start.0, start.1)
+            .ok_or(MapError::OutOfBounds)?;
+        let width = self.map.width();
+        let mut visited = vec![false; self.map.len()];
+        let mut queue = VecDeque::from([start]);
+        let mut coords = Vec::new();
+        visited[start.1 * width + start.0] = true;
+
+        while let Some((x, y)) = queue.pop_front() {
+            coords.push((x, y));
+            let neighbors = [
+                x.checked_sub(1).map(|x| (x, y)),
+                Some((x + 1, y)),
+                y.checked_sub(1).map(|y| (x, y)),
+                Some((x, y + 1)),
+            ];
+            for (nx, ny) in neighbors.into_iter().flatten() {
+                if self.map.get_tile(nx, ny) == Some(target) && !visited[ny * width + nx] {
+                    visited[ny * width + nx] = true;
+                    queue.push_back((nx, ny));
+                }
+            }
+        }
+        self.edit(coords, tile)
+    }
+
+    pub fn undo(&mut self) -> bool {
+        match self.undo.pop() {
+            Some(changes) => {
+                for change in changes.iter().rev() {
+                    self.write(change.x, change.y, change.old);
+                }
+                self.redo.push(changes);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn redo(&mut self) -> bool {
+        match self.redo.pop() {
+            Some(changes) => {
+                for change in &changes {
+                    self.write(change.x, change.y, change.new);
+                }
+                self.undo.push(changes);
+                true
+            }
+            None => false,
+        }
+    }
+
+    //Checks every coordinate before touching the map so a failed edit changes nothing.
+    //Edits that don't change any tile are not recorded.
+    fn edit(&mut self, coords: Vec<(usize, usize)>, tile: Tile) -> Result<(), MapError> {
+        let mut changes = Vec::new();
+        for (x, y) in coords {
+            let old = self.map.get_tile(x, y).ok_or(MapError::OutOfBounds)?;
+            if old != tile {
+                changes.push(TileChange {
+                    x,
+                    y,
+                    old,
+                    new: tile,
+                });
+            }
+        }
+        if changes.is_empty() {
+            return Ok(());
+        }
+        for change in &changes {
+            self.write(change.x, change.y, change.new);
+        }
+        self.undo.push(changes);
+        self.redo.clear();
+        Ok(())
+    }
+
+    //Only called with coordinates that were in bounds when the change was recorded
+    fn write(&mut self, x: usize, y: usize, tile: Tile) {
+        if let Some(slot) = self.map.get_tile_mut(x, y) {
+            *slot = tile;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tile::{Element, Shape, Special};
+
+    fn tile(element: Element) -> Tile {
+        Tile::new(None, Some(Shape::Blank), element, element)
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut history = MapHistory::new(Map::new());
+        let original = history.map().get_tile(0, 0).unwrap();
+        let hole = Tile::new(Some(Special::Hole), None, Element::Grass, Element::Grass);
+
+        history.set_tile(5, 5, hole).unwrap();
+        history
+            .fill_region((0, 0), (3, 2), tile(Element::Ice))
+            .unwrap();
+        history.flood_fill((1, 1), tile(Element::Mud)).unwrap();
+        assert_eq!(history.map().get_tile(3, 2), Some(tile(Element::Mud)));
+
+        assert!(history.undo());
+        assert!(history.undo());
+        assert_eq!(history.map().get_tile(0, 0), Some(original));
+        assert_eq!(history.map().get_tile(5, 5), Some(hole));
+
+        assert!(history.redo());
+        assert_eq!(history.map().get_tile(0, 0), Some(tile(Element::Ice)));
+        assert_eq!(history.map().get_tile(3, 2), Some(tile(Element::Ice)));
+        assert_eq!(history.map().get_tile(4, 2), Some(original));
+        assert_eq!(history.map().get_tile(5, 5), Some(hole));
+        assert!(history.can_redo());
+
+        //A new edit drops the redo stack
+        history.set_tile(10, 10, hole).unwrap();
+        assert!(!history.can_redo());
+        assert!(!history.redo());
+    }
+
+    #[test]
+    fn test_flood_fill() {
+        let mut map = Map::new();
+        for y in 0..Map::HEIGHT {
+            map.set_tile(10, y, tile(Element::Block)).unwrap();
+        }
+        let mut history = MapHistory::new(map);
+        history.flood_fill((0, 0), tile(Element::Water)).unwrap();
+
+        assert_eq!(history.map().get_tile(9, 24), Some(tile(Element::Water)));
+        assert_eq!(history.map().get_tile(10, 0), Some(tile(Element::Block)));
+        assert_ne!(history.map().get_tile(11, 0), Some(tile(Element::Water)));
+
+        assert!(history.undo());
+        assert!(!history.can_undo());
+        assert_ne!(history.map().get_tile(9, 24), Some(tile(Element::Water)));
+
+        let mut map = Map::with_size(60, 30);
+        map.set_tile(55, 0, tile(Element::Block)).unwrap();
+        let mut history = MapHistory::new(map);
+        history.flood_fill((59, 29), tile(Element::Water)).unwrap();
+        assert_eq!(history.map().get_tile(0, 0), Some(tile(Element::Water)));
+        assert_eq!(history.map().get_tile(55, 0), Some(tile(Element::Block)));
+    }
+
+    #[test]
+    fn test_out_of_bounds() {
+        let mut history = MapHistory::new(Map::new());
+        let before = history.map().get_tile(0, 0);
+        assert!(history
+            .fill_region((0, 0), (Map::WIDTH, 0), tile(Element::Ice))
+            .is_err());
+        assert_eq!(history.map().get_tile(0, 0), before);
+        assert!(history
+            .flood_fill((0, Map::HEIGHT), tile(Element::Ice))
+            .is_err());
+        assert!(!history.can_undo());
+
+        //Setting a tile to what it already is records nothing
+        history.set_tile(0, 0, before.unwrap()).unwrap();
+        assert!(!history.can_undo());
+    }
+}
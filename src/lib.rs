@@ -1,7 +1,10 @@
 pub mod array2diter;
+pub mod ball;
+pub mod direction;
 pub mod magnet;
 pub mod map;
 pub mod gamemap;
+pub mod history;
 pub mod stroke;
 pub mod tile;
 pub mod track;
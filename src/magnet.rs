@@ -1,26 +1,59 @@
+use crate::gamemap::GameMap;
 use crate::map::Map;
-use crate::tile::{Special, Tile};
+use crate::tile::Special;
+use crate::vector2d::Vector2D;
+#[cfg(feature = "image")]
+use image::{Rgba, RgbaImage};
+use rand::Rng;
 
 pub struct MagnetForces {
     forces: Vec<[i32; 2]>,
 }
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct MagnetConfig {
+    //Attract magnets pull harder within this many pixels, 0 disables the boost
+    pub near_field_radius: f32,
+    //Strength multiplier at the magnet center, fading out to 1 at the radius
+    pub near_field_boost: f32,
+}
+
+impl Default for MagnetConfig {
+    fn default() -> Self {
+        Self {
+            near_field_radius: 0.0,
+            near_field_boost: 1.0,
+        }
+    }
+}
+
+//Downhill and magnet acceleration of every pixel in one lookup, the same
+//acceleration Ball::step_with_magnets applies with the magnets sampled at the
+//pixel center
+pub struct ForceField {
+    accelerations: Vec<Vector2D<f32>>,
+}
+
 pub struct Magnet {
     repel: bool,
     i: usize,
 }
 
 impl Magnet {
-    fn extract_magnets(tiles: &[Tile]) -> Vec<Magnet> {
+    pub fn extract_magnets(map: &Map) -> Vec<Magnet> {
         let mut magnets = Vec::new();
 
-        for (i, tile) in tiles.iter().enumerate() {
-            let repel = match tile.special {
-                Some(Special::MagnetAttract) => false,
-                Some(Special::MagnetRepel) => true,
-                _ => continue,
-            };
-
-            magnets.push(Magnet { repel, i });
+        for (special, repel) in [
+            (Special::MagnetAttract, false),
+            (Special::MagnetRepel, true),
+        ] {
+            //Forces only cover the standard size, like GameMap
+            for (x, y) in map.find_all_special(special) {
+                if !Map::in_bounds(x, y) {
+                    continue;
+                }
+                let i = Map::xy_to_index(x, y);
+                magnets.push(Magnet { repel, i });
+            }
         }
 
         magnets
@@ -30,13 +63,90 @@ impl Magnet {
 impl MagnetForces {
     pub const MAGNETHEIGHT: usize = Map::HEIGHT * Map::TILESIZE / 5;
     pub const MAGNETWIDTH: usize = Map::WIDTH * Map::TILESIZE / 5;
+    //Forces are stored scaled up, this brings them to velocity units
+    pub const SCALE: f32 = 0.0005;
 
     pub fn get_force(&self, x: usize, y: usize) -> Option<[i32; 2]> {
         let index = (y * (Map::WIDTH * Map::TILESIZE / 5)) + (x / 5);
         self.forces.get(index).cloned()
     }
 
+    //Force at a pixel position, blended between the four nearest samples.
+    //Samples sit at the center of each 5x5 cell. Positions outside the map,
+    //including negative ones, feel no force.
+    pub fn get_force_at(&self, pos: Vector2D<f32>) -> Vector2D<f32> {
+        let (width, height) = (GameMap::WIDTH as f32, GameMap::HEIGHT as f32);
+        if !(0.0..width).contains(&pos.x) || !(0.0..height).contains(&pos.y) {
+            return Vector2D::zero();
+        }
+        let cell = |p: f32, cells: usize| {
+            let f = ((p - 2.0) / 5.0).clamp(0.0, (cells - 1) as f32);
+            let low = f.floor() as usize;
+            (low, (low + 1).min(cells - 1), f - low as f32)
+        };
+        let (x0, x1, tx) = cell(pos.x, Self::MAGNETWIDTH);
+        let (y0, y1, ty) = cell(pos.y, Self::MAGNETHEIGHT);
+        let sample = |x: usize, y: usize| {
+            let [force_x, force_y] = self.forces[y * Self::MAGNETWIDTH + x];
+            Vector2D::new(force_x as f32, force_y as f32)
+        };
+        let top = sample(x0, y0) * (1.0 - tx) + sample(x1, y0) * tx;
+        let bottom = sample(x0, y1) * (1.0 - tx) + sample(x1, y1) * tx;
+        top * (1.0 - ty) + bottom * ty
+    }
+
+    //get_force plus a random offset no longer than amount, so balls don't settle into perfect orbits
+    pub fn get_force_jittered(
+        &self,
+        x: usize,
+        y: usize,
+        rng: &mut impl Rng,
+        amount: f32,
+    ) -> Option<[f32; 2]> {
+        let [force_x, force_y] = self.get_force(x, y)?;
+        let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+        let length = if amount > 0.0 {
+            rng.gen_range(0.0..amount)
+        } else {
+            0.0
+        };
+        Some([
+            force_x as f32 + angle.cos() * length,
+            force_y as f32 + angle.sin() * length,
+        ])
+    }
+
+    //One pixel per sample, brighter for stronger forces. The strongest force in
+    //the field is white and no force is black.
+    #[cfg(feature = "image")]
+    pub fn render_heatmap(&self) -> RgbaImage {
+        let magnitude = |[x, y]: [i32; 2]| ((x * x + y * y) as f32).sqrt();
+        let max = self
+            .forces
+            .iter()
+            .copied()
+            .map(magnitude)
+            .fold(0.0, f32::max);
+        RgbaImage::from_fn(
+            Self::MAGNETWIDTH as u32,
+            Self::MAGNETHEIGHT as u32,
+            |x, y| {
+                let force = self.forces[y as usize * Self::MAGNETWIDTH + x as usize];
+                let value = if max > 0.0 {
+                    (magnitude(force) / max * 255.0) as u8
+                } else {
+                    0
+                };
+                Rgba([value, value, value, 255])
+            },
+        )
+    }
+
     pub fn new(magnets: &[Magnet]) -> Self {
+        MagnetForces::with_config(magnets, &MagnetConfig::default())
+    }
+
+    pub fn with_config(magnets: &[Magnet], config: &MagnetConfig) -> Self {
         let mut forces = vec![[0, 0]; Self::MAGNETWIDTH * Self::MAGNETHEIGHT];
 
         for y in (2..Map::HEIGHT * Map::TILESIZE).step_by(5) {
@@ -51,7 +161,11 @@ impl MagnetForces {
                     let distance = ((delta_x * delta_x + delta_y * delta_y) as f32).sqrt();
                     if distance <= 127.0 {
                         let normalized_x = (delta_x.abs() as f32) / distance;
-                        let strength = 127.0 - distance;
+                        let mut strength = 127.0 - distance;
+                        if !magnet.repel && distance < config.near_field_radius {
+                            let closeness = 1.0 - distance / config.near_field_radius;
+                            strength *= 1.0 + (config.near_field_boost - 1.0) * closeness;
+                        }
 
                         let mut force_x = if delta_x < 0 {
                             (-1.0 * strength * normalized_x) as i32
@@ -83,9 +197,38 @@ impl MagnetForces {
     }
 }
 
+impl ForceField {
+    pub fn new(game_map: &GameMap, magnets: &MagnetForces) -> Self {
+        let mut accelerations = Vec::with_capacity(GameMap::WIDTH * GameMap::HEIGHT);
+        for y in 0..GameMap::HEIGHT {
+            for x in 0..GameMap::WIDTH {
+                let center = Vector2D::new(x as f32 + 0.5, y as f32 + 0.5);
+                accelerations.push(
+                    game_map.downhill_at(x, y) + magnets.get_force_at(center) * MagnetForces::SCALE,
+                );
+            }
+        }
+        Self { accelerations }
+    }
+
+    //Zero outside the map
+    pub fn get_acceleration(&self, pos: Vector2D<f32>) -> Vector2D<f32> {
+        if pos.x < 0.0 || pos.y < 0.0 {
+            return Vector2D::zero();
+        }
+        let (x, y) = (pos.x as usize, pos.y as usize);
+        if x < GameMap::WIDTH && y < GameMap::HEIGHT {
+            self.accelerations[y * GameMap::WIDTH + x]
+        } else {
+            Vector2D::zero()
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::tile::{Element, Tile};
 
     #[test]
     fn test_get_force() {
@@ -99,6 +242,114 @@ mod tests {
         assert_eq!(magnet_forces.get_force(100, 100), None);
     }
 
+    #[test]
+    fn test_get_force_jittered() {
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+
+        let forces = vec![[10, -4]; MagnetForces::MAGNETWIDTH * MagnetForces::MAGNETHEIGHT];
+        let magnet_forces = MagnetForces { forces };
+        let mut rng = StdRng::seed_from_u64(3);
+
+        let mut jittered = false;
+        for _ in 0..100 {
+            let [x, y] = magnet_forces
+                .get_force_jittered(5, 10, &mut rng, 0.5)
+                .unwrap();
+            let offset = ((x - 10.0).powi(2) + (y + 4.0).powi(2)).sqrt();
+            assert!(offset <= 0.5);
+            jittered |= offset > 0.0;
+        }
+        assert!(jittered);
+
+        assert_eq!(
+            magnet_forces.get_force_jittered(5, 10, &mut rng, 0.0),
+            Some([10.0, -4.0])
+        );
+        assert_eq!(magnet_forces.get_force(5, 10), Some([10, -4]));
+        assert_eq!(
+            magnet_forces.get_force_jittered(100, 100, &mut rng, 0.5),
+            None
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "image")]
+    fn test_render_heatmap() {
+        let mut forces = vec![[0, 0]; MagnetForces::MAGNETWIDTH * MagnetForces::MAGNETHEIGHT];
+        forces[MagnetForces::MAGNETWIDTH * 2 + 3] = [30, -40];
+        forces[MagnetForces::MAGNETWIDTH * 2 + 4] = [3, 4];
+        let image = MagnetForces { forces }.render_heatmap();
+
+        assert_eq!(
+            image.dimensions(),
+            (
+                MagnetForces::MAGNETWIDTH as u32,
+                MagnetForces::MAGNETHEIGHT as u32
+            )
+        );
+        let brightness = |x, y| image.get_pixel(x, y)[0];
+        assert_eq!(brightness(3, 2), 255);
+        assert!(brightness(4, 2) > brightness(0, 0));
+        assert!(brightness(3, 2) > brightness(4, 2));
+        assert_eq!(brightness(0, 0), 0);
+
+        let empty = MagnetForces::new(&[]).render_heatmap();
+        assert!(empty.pixels().all(|pixel| pixel[0] == 0));
+    }
+
+    #[test]
+    fn test_force_field() {
+        let assets = crate::gamemap::Assets::procedural(Map::TILESIZE);
+        let mut map = Map::new();
+        let speed = Tile::new(
+            None,
+            Some(crate::tile::Shape::Blank),
+            Element::SpeedS,
+            Element::Grass,
+        );
+        map.set_tile(5, 5, speed).unwrap();
+        let game_map = GameMap::from_map(&map, &assets);
+        let field = ForceField::new(&game_map, &MagnetForces::new(&[]));
+
+        let over_speed = field.get_acceleration(Vector2D::new(82.5, 80.0));
+        assert!(over_speed.approx_eq(&Element::SpeedS.get_downhill_speed(), 1e-6));
+        assert!(over_speed.y > 0.0);
+        let grass = field.get_acceleration(Vector2D::new(20.0, 20.0));
+        assert_eq!((grass.x, grass.y), (0.0, 0.0));
+        let outside = field.get_acceleration(Vector2D::new(-1.0, GameMap::HEIGHT as f32));
+        assert_eq!((outside.x, outside.y), (0.0, 0.0));
+
+        let magnets = MagnetForces::new(&[Magnet {
+            repel: false,
+            i: Map::xy_to_index(20, 10),
+        }]);
+        let field = ForceField::new(&game_map, &magnets);
+        let pos = Vector2D::new(250.5, 160.5);
+        let pulled = field.get_acceleration(pos);
+        assert!(pulled.approx_eq(&(magnets.get_force_at(pos) * MagnetForces::SCALE), 1e-6));
+        assert!(pulled.x > 0.0);
+    }
+
+    #[test]
+    fn test_extract_magnets() {
+        let mut map = Map::new();
+        let magnet = |special| Tile::new(Some(special), None, Element::Grass, Element::Grass);
+        map.set_tile(3, 4, magnet(Special::MagnetAttract)).unwrap();
+        map.set_tile(10, 2, magnet(Special::MagnetRepel)).unwrap();
+
+        let magnets = Magnet::extract_magnets(&map);
+        assert_eq!(magnets.len(), 2);
+        assert!(!magnets[0].repel);
+        assert_eq!(magnets[0].i, Map::xy_to_index(3, 4));
+        assert!(magnets[1].repel);
+        assert_eq!(magnets[1].i, Map::xy_to_index(10, 2));
+
+        map.resize(60, 30);
+        map.set_tile(55, 0, magnet(Special::MagnetAttract)).unwrap();
+        assert_eq!(Magnet::extract_magnets(&map).len(), 2);
+    }
+
     #[test]
     fn test_calculate_forces() {
         let magnets = vec![
@@ -119,4 +370,72 @@ mod tests {
         assert_eq!(magnet_forces.get_force(121 * 5, 0), Some([5, 0]));
         assert_eq!(magnet_forces.forces[10879], [-29, 93]);
     }
+
+    #[test]
+    fn test_near_field_boost() {
+        //Sample point (147, 157) is about 11 pixels from the magnet center (158, 158)
+        let index = (157 / 5) * MagnetForces::MAGNETWIDTH + 147 / 5;
+        let config = MagnetConfig {
+            near_field_radius: 30.0,
+            near_field_boost: 3.0,
+        };
+        let attract = [Magnet {
+            repel: false,
+            i: Map::xy_to_index(10, 10),
+        }];
+
+        let linear = MagnetForces::new(&attract).forces[index];
+        let boosted = MagnetForces::with_config(&attract, &config).forces[index];
+        assert!(linear[0] > 0);
+        assert!(boosted[0] > linear[0]);
+        assert!(boosted[1] >= linear[1]);
+
+        //Far away the boost doesn't apply
+        let far = (157 / 5) * MagnetForces::MAGNETWIDTH + 102 / 5;
+        assert_eq!(
+            MagnetForces::new(&attract).forces[far],
+            MagnetForces::with_config(&attract, &config).forces[far]
+        );
+
+        let repel = [Magnet {
+            repel: true,
+            i: Map::xy_to_index(10, 10),
+        }];
+        assert_eq!(
+            MagnetForces::new(&repel).forces,
+            MagnetForces::with_config(&repel, &config).forces
+        );
+    }
+
+    #[test]
+    fn test_get_force_at() {
+        let mut forces = vec![[0, 0]; MagnetForces::MAGNETWIDTH * MagnetForces::MAGNETHEIGHT];
+        forces[MagnetForces::MAGNETWIDTH + 1] = [10, -20];
+        forces[MagnetForces::MAGNETWIDTH + 2] = [20, 0];
+        forces[MagnetForces::MAGNETWIDTH * MagnetForces::MAGNETHEIGHT - 1] = [4, 4];
+        let magnet_forces = MagnetForces { forces };
+
+        //On a sample and halfway between two
+        let force = magnet_forces.get_force_at(Vector2D::new(7.0, 7.0));
+        assert_eq!((force.x, force.y), (10.0, -20.0));
+        let force = magnet_forces.get_force_at(Vector2D::new(9.5, 7.0));
+        assert_eq!((force.x, force.y), (15.0, -10.0));
+        let force = magnet_forces.get_force_at(Vector2D::new(7.0, 9.5));
+        assert_eq!((force.x, force.y), (5.0, -10.0));
+
+        //Past the last sample the edge value holds
+        let edge = Vector2D::new(GameMap::WIDTH as f32 - 0.5, GameMap::HEIGHT as f32 - 0.5);
+        let force = magnet_forces.get_force_at(edge);
+        assert_eq!((force.x, force.y), (4.0, 4.0));
+
+        for pos in [
+            Vector2D::new(-0.5, 7.0),
+            Vector2D::new(7.0, -3.0),
+            Vector2D::new(GameMap::WIDTH as f32, 7.0),
+            Vector2D::new(7.0, GameMap::HEIGHT as f32 + 10.0),
+        ] {
+            let force = magnet_forces.get_force_at(pos);
+            assert_eq!((force.x, force.y), (0.0, 0.0));
+        }
+    }
 }
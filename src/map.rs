@@ -1,17 +1,36 @@
-use crate::tile::{Tile, TileCreationError};
+use crate::array2diter::Array2DIterator;
+use crate::tile::{Element, PackedTile, Shape, Special, TeamColor, Tile, TileCreationError};
+use crate::track::Settings;
 use crate::vector2d::Vector2D;
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
+use rand::Rng;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::BufRead;
 use std::num::ParseIntError;
 use thiserror::Error;
 
-#[derive(Debug, Default)]
+//Tracks are always Map::WIDTH x Map::HEIGHT tiles. Editors can resize a map,
+//but encode refuses other sizes and GameMap crops or pads them to the standard one.
+#[derive(Debug, Default, PartialEq, Clone)]
 pub struct Map {
     pub tiles: Vec<Tile>,
     pub ads: Vec<Ad>,
+    width: usize,
+    height: usize,
+}
+
+//Same as Map but with each tile packed into 4 bytes
+#[derive(Debug, Default)]
+pub struct PackedMap {
+    pub tiles: Vec<PackedTile>,
+    pub ads: Vec<Ad>,
+    width: usize,
+    height: usize,
 }
 
-#[derive(Debug, FromPrimitive, PartialEq)]
+#[derive(Debug, FromPrimitive, PartialEq, Copy, Clone)]
 pub enum AdSize {
     Small,
     Medium,
@@ -19,25 +38,143 @@ pub enum AdSize {
     Full,
 }
 
-#[derive(Debug)]
+//What decode does with input that doesn't fill the grid exactly
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum DecodeMode {
+    //Tiles past the end of the input stay default grass, extra input is ignored
+    Lenient,
+    //Both are errors, as they usually mean the map is corrupt
+    Strict,
+}
+
+//Back-reference codes encode_with may write. decode reads both.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Codec {
+    //Only D-I, readable by every decoder of the track format
+    Classic,
+    //Also J-L and the row copy M, smaller but only readable by this crate
+    Extended,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum SymmetryAxis {
+    //Mirrors left and right halves
+    Horizontal,
+    //Mirrors top and bottom halves
+    Vertical,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Ad {
     size: AdSize,
     x: i32,
     y: i32,
 }
 
+#[derive(Debug, PartialEq)]
+pub enum AdIssue {
+    OutOfBounds(usize),
+    Overlap(usize, usize),
+}
+
+#[derive(Debug, PartialEq, Error)]
+pub enum MapValidationIssue {
+    #[error("More than one {0:?} start position")]
+    DuplicateTeamStart(TeamColor),
+    #[error("{count} team start positions, expected {min} to {max}")]
+    TeamStartCount { count: usize, min: i32, max: i32 },
+    #[error("{width}x{height} map, expected {w}x{h}", w = Map::WIDTH, h = Map::HEIGHT)]
+    Size { width: usize, height: usize },
+    #[error("{0} tiles, expected {w}x{h}", w = Map::WIDTH, h = Map::HEIGHT)]
+    TileCount(usize),
+    #[error("No start position")]
+    MissingStart,
+    #[error("No hole")]
+    MissingHole,
+}
+
 #[derive(Debug, Error)]
 pub enum MapError {
     #[error("Out of Bounds")]
     OutOfBounds,
     #[error("Unexpected char {0}")]
     Unexpected(char),
+    #[error("Unexpected char {ch} at ({x}, {y})")]
+    UnexpectedAt { ch: char, x: usize, y: usize },
+    #[error("Back-reference {ch} at ({x}, {y}) points outside the map")]
+    InvalidReference { ch: char, x: usize, y: usize },
     #[error("Unexpected end of line")]
     UnexpectedEol,
     #[error("TileCreation Error")]
     TileCreationError(#[from] TileCreationError),
     #[error("ParseInt Error")]
     ParseIntError(#[from] ParseIntError),
+    #[error("IO Error:{0}")]
+    IOError(#[from] std::io::Error),
+    #[error("Invalid UTF-8")]
+    InvalidUtf8,
+    #[error("Expected {expected} tiles, got {got}")]
+    InvalidLength { expected: usize, got: usize },
+    #[error("Input ended before tile ({x}, {y})")]
+    IncompleteGrid { x: usize, y: usize },
+    #[error("Input left over after the last tile")]
+    TrailingData,
+    #[error("Not a binary map or unsupported version")]
+    InvalidHeader,
+    #[error("Invalid ad size {0}")]
+    InvalidAdSize(u8),
+    #[error("Code {0} has no character")]
+    InvalidCode(i32),
+    #[error("Tile without a special has no shape")]
+    MissingShape,
+    #[error("Only {w}x{h} maps can be encoded, not {width}x{height}", w = Map::WIDTH, h = Map::HEIGHT)]
+    UnsupportedSize { width: usize, height: usize },
+}
+
+//Reads chars one at a time so decoding doesn't need the whole map in memory
+struct ReaderChars<R> {
+    reader: R,
+}
+
+impl<R: BufRead> ReaderChars<R> {
+    fn read_byte(&mut self) -> Result<Option<u8>, MapError> {
+        let byte = self.reader.fill_buf()?.first().copied();
+        if byte.is_some() {
+            self.reader.consume(1);
+        }
+        Ok(byte)
+    }
+
+    fn read_char(&mut self) -> Result<Option<char>, MapError> {
+        let first = match self.read_byte()? {
+            Some(byte) => byte,
+            None => return Ok(None),
+        };
+        let width = match first {
+            0x00..=0x7F => 1,
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => return Err(MapError::InvalidUtf8),
+        };
+        let mut buf = [first, 0, 0, 0];
+        for byte in buf.iter_mut().take(width).skip(1) {
+            *byte = self.read_byte()?.ok_or(MapError::InvalidUtf8)?;
+        }
+        std::str::from_utf8(&buf[..width])
+            .ok()
+            .and_then(|s| s.chars().next())
+            .map(Some)
+            .ok_or(MapError::InvalidUtf8)
+    }
+}
+
+impl<R: BufRead> Iterator for ReaderChars<R> {
+    type Item = Result<char, MapError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_char().transpose()
+    }
 }
 
 impl AdSize {
@@ -52,48 +189,134 @@ impl AdSize {
 }
 
 impl Ad {
+    pub const DIGITS: usize = 2;
+
     pub fn from_string(input: &str) -> Result<Vec<Ad>, MapError> {
+        Ad::from_string_with_digits(input, Ad::DIGITS)
+    }
+
+    //Each ad is a size char followed by x and y, each `digits` wide. Coordinates
+    //are not checked against the map, validate_ads reports ads outside it.
+    pub fn from_string_with_digits(input: &str, digits: usize) -> Result<Vec<Ad>, MapError> {
         let mut ads = Vec::new();
-        for chunk in input.chars().collect::<Vec<char>>().chunks(5) {
+        for chunk in input.chars().collect::<Vec<char>>().chunks(1 + digits * 2) {
             if let Some((first_char, last_chars)) = chunk.split_first() {
                 let ad_code = Map::char_to_code(*first_char)
                     .ok_or_else(|| MapError::Unexpected(*first_char))?;
-                if last_chars.len() != 4 {
+                if last_chars.len() != digits * 2 {
                     return Err(MapError::UnexpectedEol);
                 }
                 let size: AdSize = FromPrimitive::from_i32(ad_code)
                     .ok_or_else(|| MapError::Unexpected(*first_char))?;
-                let x = last_chars[..2].iter().collect::<String>().parse::<i32>()?;
-                let y = last_chars[2..].iter().collect::<String>().parse::<i32>()?;
+                let x = last_chars[..digits]
+                    .iter()
+                    .collect::<String>()
+                    .parse::<i32>()?;
+                let y = last_chars[digits..]
+                    .iter()
+                    .collect::<String>()
+                    .parse::<i32>()?;
                 ads.push(Ad { size, x, y })
             }
         }
         Ok(ads)
     }
+
+    //Inverse of from_string for a single ad. Sizes are 0-3, always A-D.
+    pub fn encode(&self) -> String {
+        format!(
+            "{}{:02}{:02}",
+            (b'A' + self.size as u8) as char,
+            self.x,
+            self.y
+        )
+    }
+
+    //Left, top, right and bottom edges in tiles, right and bottom exclusive
+    pub fn footprint(&self) -> (i32, i32, i32, i32) {
+        let (width, height) = AdSize::get_ad_size(&self.size);
+        (
+            self.x,
+            self.y,
+            self.x + width as i32,
+            self.y + height as i32,
+        )
+    }
 }
 
 impl Map {
+    pub const BINARY_MAGIC: &[u8; 4] = b"MGMP";
+    pub const BINARY_VERSION: u8 = 2;
     pub const HEIGHT: usize = 25;
     pub const TILESIZE: usize = 15;
     pub const WIDTH: usize = 49;
 
     pub fn new() -> Self {
+        Map::with_size(Map::WIDTH, Map::HEIGHT)
+    }
+
+    pub fn with_size(width: usize, height: usize) -> Self {
         Self {
-            tiles: vec![Tile::default(); Map::WIDTH * Map::HEIGHT],
+            tiles: vec![Tile::default(); width * height],
             ads: Vec::new(),
+            width,
+            height,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    //Keeps the tiles that fit at the same coordinates and fills new cells with
+    //the default tile. Ads are kept, validate_ads reports the ones left outside.
+    pub fn resize(&mut self, new_width: usize, new_height: usize) {
+        let mut tiles = vec![Tile::default(); new_width * new_height];
+        for y in 0..self.height.min(new_height) {
+            for x in 0..self.width.min(new_width) {
+                tiles[y * new_width + x] = self.tiles[y * self.width + x];
+            }
         }
+        self.tiles = tiles;
+        self.width = new_width;
+        self.height = new_height;
+    }
+
+    fn index(&self, x: usize, y: usize) -> Option<usize> {
+        (x < self.width && y < self.height).then(|| y * self.width + x)
+    }
+
+    fn is_standard_size(&self) -> bool {
+        self.width == Map::WIDTH && self.height == Map::HEIGHT
     }
 
     pub fn from_string(input: &str) -> Result<Map, MapError> {
-        let mut split = input.split(",Ads:");
-        let map_str = split.next().unwrap_or("");
-        let ads_str = split.next().unwrap_or("");
-        let decompressed = Map::decompress(map_str);
-        let mut map = Map::decode(decompressed)?;
-        map.ads = Ad::from_string(ads_str)?;
+        let (mut map, ads) = Map::from_string_lenient(input)?;
+        map.ads = ads?;
         Ok(map)
     }
 
+    //Inverse of from_string, the format of the "T" line in track files
+    pub fn to_track_string(&self) -> Result<String, MapError> {
+        let ads: String = self.ads.iter().map(Ad::encode).collect();
+        Ok(format!("{},Ads:{}", Map::compress(&self.encode()?), ads))
+    }
+
+    //Ad errors are returned separately so a valid map survives a broken ads section.
+    //The returned map has no ads.
+    pub fn from_string_lenient(input: &str) -> Result<(Map, Result<Vec<Ad>, MapError>), MapError> {
+        //Ads never contain commas, anything after the next one is not ours
+        let (map_str, ads_str) = input.split_once(",Ads:").unwrap_or((input, ""));
+        let ads_str = ads_str.split(',').next().unwrap_or("");
+        let decompressed = Map::decompress(map_str);
+        let map = Map::decode(decompressed)?;
+        Ok((map, Ad::from_string(ads_str)))
+    }
+
     pub fn decompress(input: &str) -> String {
         let mut output = String::new();
         let mut count = String::new();
@@ -102,7 +325,12 @@ impl Map {
             if c.is_digit(10) {
                 count.push(c);
             } else {
-                let repeat_count = count.parse::<usize>().unwrap_or(1);
+                //No run can be longer than the map, bigger counts would only
+                //allocate output that decode never reads
+                let repeat_count = count
+                    .parse::<usize>()
+                    .unwrap_or(1)
+                    .min(Map::WIDTH * Map::HEIGHT);
                 output.push_str(&c.to_string().repeat(repeat_count));
                 count.clear();
             }
@@ -131,89 +359,658 @@ impl Map {
         compressed_string
     }
 
+    pub fn validate_ads(&self) -> Vec<AdIssue> {
+        let mut issues = Vec::new();
+
+        for (i, ad) in self.ads.iter().enumerate() {
+            let (left, top, right, bottom) = ad.footprint();
+            if left < 0 || top < 0 || right > self.width as i32 || bottom > self.height as i32 {
+                issues.push(AdIssue::OutOfBounds(i));
+            }
+        }
+
+        for (i, a) in self.ads.iter().enumerate() {
+            for (j, b) in self.ads.iter().enumerate().skip(i + 1) {
+                let (a_left, a_top, a_right, a_bottom) = a.footprint();
+                let (b_left, b_top, b_right, b_bottom) = b.footprint();
+                if a_left < b_right && b_left < a_right && a_top < b_bottom && b_top < a_bottom {
+                    issues.push(AdIssue::Overlap(i, j));
+                }
+            }
+        }
+
+        issues
+    }
+
+    //Bounds of the standard size, get_tile also works for resized maps
+    #[inline]
+    pub fn in_bounds(x: usize, y: usize) -> bool {
+        x < Map::WIDTH && y < Map::HEIGHT
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+
+    //Compares only the layout, ignoring ads
+    pub fn tiles_eq(&self, other: &Map) -> bool {
+        self.tiles == other.tiles
+    }
+
+    #[inline]
     pub fn set_tile(&mut self, x: usize, y: usize, tile: Tile) -> Result<(), MapError> {
-        if x < Map::WIDTH && y < Map::HEIGHT {
-            self.tiles[y * Map::WIDTH + x] = tile;
-            Ok(())
-        } else {
-            Err(MapError::OutOfBounds)
+        match self.get_tile_mut(x, y) {
+            Some(slot) => {
+                *slot = tile;
+                Ok(())
+            }
+            None => Err(MapError::OutOfBounds),
         }
     }
 
+    #[inline]
     pub fn get_tile(&self, x: usize, y: usize) -> Option<Tile> {
-        if x < Map::WIDTH && y < Map::HEIGHT {
-            Some(self.tiles[y * Map::WIDTH + x])
+        self.index(x, y).and_then(|i| self.tiles.get(i)).copied()
+    }
+
+    //The map tile under a pixel position, unlike GameMap::get_tile which is per pixel
+    pub fn tile_at_pixel(&self, pos: Vector2D<f32>) -> Option<Tile> {
+        if pos.x < 0.0 || pos.y < 0.0 {
+            return None;
+        }
+        self.get_tile(
+            pos.x as usize / Map::TILESIZE,
+            pos.y as usize / Map::TILESIZE,
+        )
+    }
+
+    #[inline]
+    pub fn get_tile_mut(&mut self, x: usize, y: usize) -> Option<&mut Tile> {
+        self.index(x, y).and_then(|i| self.tiles.get_mut(i))
+    }
+
+    pub fn swap_tiles(&mut self, a: (usize, usize), b: (usize, usize)) -> Result<(), MapError> {
+        let len = self.tiles.len();
+        let index = |(x, y): (usize, usize)| {
+            self.index(x, y)
+                .filter(|&i| i < len)
+                .ok_or(MapError::OutOfBounds)
+        };
+        let (a, b) = (index(a)?, index(b)?);
+        self.tiles.swap(a, b);
+        Ok(())
+    }
+
+    //A tile counts as solid if any solid element is on it: a solid special, a solid
+    //background, or a solid foreground under a non-blank shape. Partially covered
+    //tiles count as fully solid.
+    pub fn solid_tile_count(&self) -> usize {
+        self.tiles
+            .iter()
+            .filter(|tile| {
+                tile.special.is_some_and(|special| special.is_solid())
+                    || tile.background.is_solid()
+                    || (tile.shape.is_some_and(|shape| shape != Shape::Blank)
+                        && tile.foreground.is_solid())
+            })
+            .count()
+    }
+
+    //Share of tiles that are not solid, 0 for a map without tiles
+    pub fn open_ratio(&self) -> f32 {
+        if self.tiles.is_empty() {
+            return 0.0;
+        }
+        1.0 - self.solid_tile_count() as f32 / self.tiles.len() as f32
+    }
+
+    //Every tile with its x and y, row by row
+    pub fn iter_tiles(&self) -> Array2DIterator<'_, Tile> {
+        Array2DIterator::new(&self.tiles, self.width)
+    }
+
+    //Calls f on every tile with its x and y, row by row
+    pub fn for_each_tile_mut(&mut self, mut f: impl FnMut(&mut Tile, usize, usize)) {
+        let width = self.width;
+        for (i, tile) in self.tiles.iter_mut().enumerate() {
+            f(tile, i % width, i / width);
+        }
+    }
+
+    pub fn find_all_special(&self, special: Special) -> Vec<(usize, usize)> {
+        self.tiles
+            .iter()
+            .enumerate()
+            .filter(|(_, tile)| tile.special == Some(special))
+            .map(|(i, _)| self.index_to_xy_sized(i))
+            .collect()
+    }
+
+    //Matches the background, or the foreground when a shape is drawn with it
+    pub fn find_all_element(&self, element: Element) -> Vec<(usize, usize)> {
+        self.tiles
+            .iter()
+            .enumerate()
+            .filter(|(_, tile)| {
+                tile.background == element
+                    || (tile.shape.is_some_and(|shape| shape != Shape::Blank)
+                        && tile.foreground == element)
+            })
+            .map(|(i, _)| self.index_to_xy_sized(i))
+            .collect()
+    }
+
+    pub fn start_positions(&self) -> Vec<(Special, (usize, usize))> {
+        self.tiles
+            .iter()
+            .enumerate()
+            .filter_map(|(i, tile)| match tile.special {
+                Some(special) if special.is_start_position() => {
+                    Some((special, self.index_to_xy_sized(i)))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    //Structural checks that every playable map has to pass
+    pub fn validate(&self) -> Vec<MapValidationIssue> {
+        if !self.is_standard_size() {
+            return vec![MapValidationIssue::Size {
+                width: self.width,
+                height: self.height,
+            }];
+        }
+        if self.tiles.len() != Map::WIDTH * Map::HEIGHT {
+            return vec![MapValidationIssue::TileCount(self.tiles.len())];
+        }
+
+        let mut issues = Vec::new();
+        if self.start_positions().is_empty() {
+            issues.push(MapValidationIssue::MissingStart);
+        }
+        if self.find_all_special(Special::Hole).is_empty() {
+            issues.push(MapValidationIssue::MissingHole);
+        }
+        issues
+    }
+
+    //Maps without colored start positions have nothing to check
+    pub fn validate_team_starts(&self, settings: &Settings) -> Result<(), MapValidationIssue> {
+        let mut colors = Vec::new();
+        for (special, _) in self.start_positions() {
+            if let Some(color) = special.team_color() {
+                if colors.contains(&color) {
+                    return Err(MapValidationIssue::DuplicateTeamStart(color));
+                }
+                colors.push(color);
+            }
+        }
+
+        let count = colors.len();
+        let players = settings.player_range();
+        if count > 0 && !players.contains(&(count as i32)) {
+            return Err(MapValidationIssue::TeamStartCount {
+                count,
+                min: *players.start(),
+                max: *players.end(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn find_teleport_exits(&self, start: Special) -> Vec<(usize, usize)> {
+        match start.get_matching_teleport() {
+            Some(exit) => self.find_all_special(exit),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn path_exists(&self, from: (usize, usize), to: (usize, usize)) -> bool {
+        let mut visited = vec![false; self.tiles.len()];
+        let mut queue = VecDeque::new();
+        if let Some(index) = self.index(from.0, from.1) {
+            visited[index] = true;
+            queue.push_back(from);
+        }
+
+        while let Some((x, y)) = queue.pop_front() {
+            if (x, y) == to {
+                return true;
+            }
+            let neighbors = [
+                (x.wrapping_sub(1), y),
+                (x + 1, y),
+                (x, y.wrapping_sub(1)),
+                (x, y + 1),
+            ];
+            for (nx, ny) in neighbors {
+                if let (Some(tile), Some(index)) = (self.get_tile(nx, ny), self.index(nx, ny)) {
+                    if !visited[index] && tile.is_open() {
+                        visited[index] = true;
+                        queue.push_back((nx, ny));
+                    }
+                }
+            }
+        }
+
+        false
+    }
+
+    pub fn random(rng: &mut impl Rng) -> Map {
+        let mut map = Map::random_fill(rng);
+
+        let start = Map::random_interior(rng);
+        let mut hole = Map::random_interior(rng);
+        while hole == start {
+            hole = Map::random_interior(rng);
+        }
+        map.finish_random(start, hole);
+        map
+    }
+
+    pub fn random_symmetric(rng: &mut impl Rng, axis: SymmetryAxis) -> Map {
+        let mut map = Map::random_fill(rng);
+        let mirrored = match axis {
+            SymmetryAxis::Horizontal => map.flip_horizontal(),
+            SymmetryAxis::Vertical => map.flip_vertical(),
+        };
+
+        for y in 0..Map::HEIGHT {
+            for x in 0..Map::WIDTH {
+                let index = Map::xy_to_index(x, y);
+                let (position, middle) = match axis {
+                    SymmetryAxis::Horizontal => (x, Map::WIDTH / 2),
+                    SymmetryAxis::Vertical => (y, Map::HEIGHT / 2),
+                };
+                if position > middle {
+                    map.tiles[index] = mirrored.tiles[index];
+                } else if position == middle && map.tiles[index] != mirrored.tiles[index] {
+                    //Tiles on the axis have to be their own mirror image
+                    map.tiles[index] = Tile::default();
+                }
+            }
+        }
+
+        //Start and hole both sit on the axis so they map onto themselves
+        let (start, hole) = loop {
+            let (start, hole) = match axis {
+                SymmetryAxis::Horizontal => (
+                    (Map::WIDTH / 2, rng.gen_range(1..Map::HEIGHT - 1)),
+                    (Map::WIDTH / 2, rng.gen_range(1..Map::HEIGHT - 1)),
+                ),
+                SymmetryAxis::Vertical => (
+                    (rng.gen_range(1..Map::WIDTH - 1), Map::HEIGHT / 2),
+                    (rng.gen_range(1..Map::WIDTH - 1), Map::HEIGHT / 2),
+                ),
+            };
+            if start != hole {
+                break (start, hole);
+            }
+        };
+        map.finish_random(start, hole);
+        map
+    }
+
+    fn random_fill(rng: &mut impl Rng) -> Map {
+        let mut map = Map::new();
+        let block = Tile::new(None, Some(Shape::Blank), Element::Block, Element::Block);
+
+        for y in 0..Map::HEIGHT {
+            for x in 0..Map::WIDTH {
+                let tile = if x == 0 || y == 0 || x == Map::WIDTH - 1 || y == Map::HEIGHT - 1 {
+                    block
+                } else {
+                    Map::random_tile(rng)
+                };
+                map.tiles[Map::xy_to_index(x, y)] = tile;
+            }
+        }
+        map
+    }
+
+    //Maps have one start and one hole, these move them instead of adding another.
+    //The old tile keeps its elements and the new one keeps its background.
+    pub fn set_start(&mut self, x: usize, y: usize) -> Result<(), MapError> {
+        self.place_unique(Special::StartPosition, x, y)
+    }
+
+    pub fn set_hole(&mut self, x: usize, y: usize) -> Result<(), MapError> {
+        self.place_unique(Special::Hole, x, y)
+    }
+
+    fn place_unique(&mut self, special: Special, x: usize, y: usize) -> Result<(), MapError> {
+        let tile = self.get_tile(x, y).ok_or(MapError::OutOfBounds)?;
+        for (old_x, old_y) in self.find_all_special(special) {
+            if let Some(old) = self.get_tile_mut(old_x, old_y) {
+                *old = old.with_shape(Shape::Blank);
+            }
+        }
+        self.set_tile(x, y, tile.with_special(special))
+    }
+
+    fn finish_random(&mut self, start: (usize, usize), hole: (usize, usize)) {
+        self.tiles[Map::xy_to_index(start.0, start.1)] = Tile::default();
+        self.tiles[Map::xy_to_index(hole.0, hole.1)] = Tile::default();
+        if !self.path_exists(start, hole) {
+            self.carve_path(start, hole);
+        }
+
+        //Both are interior tiles, always in bounds
+        let _ = self.set_start(start.0, start.1);
+        let _ = self.set_hole(hole.0, hole.1);
+    }
+
+    pub fn flip_horizontal(&self) -> Map {
+        let mut map = Map::with_size(self.width, self.height);
+        map.for_each_tile_mut(|tile, x, y| {
+            *tile = self.tiles[y * self.width + self.width - 1 - x].flip_horizontal();
+        });
+        for ad in &self.ads {
+            let (width, _) = AdSize::get_ad_size(&ad.size);
+            map.ads.push(Ad {
+                size: ad.size,
+                x: self.width as i32 - ad.x - width as i32,
+                y: ad.y,
+            });
+        }
+        map
+    }
+
+    pub fn flip_vertical(&self) -> Map {
+        let mut map = Map::with_size(self.width, self.height);
+        for (tile, x, y) in self.iter_tiles() {
+            map.tiles[(self.height - 1 - y) * self.width + x] = tile.flip_vertical();
+        }
+        for ad in &self.ads {
+            let (_, height) = AdSize::get_ad_size(&ad.size);
+            map.ads.push(Ad {
+                size: ad.size,
+                x: ad.x,
+                y: self.height as i32 - ad.y - height as i32,
+            });
+        }
+        map
+    }
+
+    fn random_tile(rng: &mut impl Rng) -> Tile {
+        const GROUND: [Element; 6] = [
+            Element::Dirt,
+            Element::Mud,
+            Element::Ice,
+            Element::WaterSwamp,
+            Element::Water,
+            Element::Block,
+        ];
+        let roll = rng.gen_range(0..100);
+        let background = match roll {
+            0..=69 => Element::Grass,
+            _ => GROUND[rng.gen_range(0..GROUND.len())],
+        };
+        if background == Element::Grass && roll < 8 {
+            let shape = Shape::all()[rng.gen_range(1..Shape::all().len())];
+            Tile::new(None, Some(shape), Element::Grass, Element::Block)
         } else {
-            None
+            Tile::new(None, Some(Shape::Blank), background, background)
+        }
+    }
+
+    fn random_interior(rng: &mut impl Rng) -> (usize, usize) {
+        (
+            rng.gen_range(1..Map::WIDTH - 1),
+            rng.gen_range(1..Map::HEIGHT - 1),
+        )
+    }
+
+    //Clears an L-shaped grass corridor, first along x and then along y
+    fn carve_path(&mut self, from: (usize, usize), to: (usize, usize)) {
+        let (mut x, mut y) = from;
+        loop {
+            self.tiles[Map::xy_to_index(x, y)] = Tile::default();
+            if x != to.0 {
+                x = if x < to.0 { x + 1 } else { x - 1 };
+            } else if y != to.1 {
+                y = if y < to.1 { y + 1 } else { y - 1 };
+            } else {
+                break;
+            }
         }
     }
 
+    //index_to_xy and xy_to_index assume the standard size, like magnet indices
+    #[inline]
     pub fn index_to_xy(index: usize) -> (usize, usize) {
         let y = index / (Map::WIDTH);
         let x = index % (Map::WIDTH);
         (x, y)
     }
 
+    #[inline]
     pub fn xy_to_index(x: usize, y: usize) -> usize {
         y * Map::WIDTH + x
     }
 
+    fn index_to_xy_sized(&self, index: usize) -> (usize, usize) {
+        (index % self.width, index / self.width)
+    }
+
+    pub fn to_ascii(&self) -> String {
+        let mut output = String::with_capacity((self.width + 1) * self.height);
+        for row in self.tiles.chunks(self.width.max(1)) {
+            output.extend(row.iter().map(Tile::to_ascii_char));
+            output.push('\n');
+        }
+        output
+    }
+
+    pub fn from_ascii(input: &str) -> Result<Map, MapError> {
+        let mut map = Map::new();
+        let mut lines = input.lines();
+
+        for y in 0..Map::HEIGHT {
+            let mut chars = lines.next().ok_or(MapError::UnexpectedEol)?.chars();
+            for x in 0..Map::WIDTH {
+                let c = chars.next().ok_or(MapError::UnexpectedEol)?;
+                let tile = if let Some(special) = Special::from_char(c) {
+                    Tile::new(Some(special), None, Element::Grass, Element::Grass)
+                } else if let Some(element) = Element::from_char(c) {
+                    Tile::new(None, Some(Shape::Blank), element, Element::Grass)
+                } else {
+                    return Err(MapError::UnexpectedAt { ch: c, x, y });
+                };
+                map.set_tile(x, y, tile)?;
+            }
+            if chars.next().is_some() {
+                return Err(MapError::OutOfBounds);
+            }
+        }
+        //Extra rows are as wrong as extra columns, trailing empty lines are fine
+        if lines.any(|line| !line.is_empty()) {
+            return Err(MapError::OutOfBounds);
+        }
+
+        Ok(map)
+    }
+
+    //Row major, one code per tile, without ads
+    pub fn to_tile_codes(&self) -> Vec<i32> {
+        self.tiles.iter().map(Tile::to_tile_code).collect()
+    }
+
+    pub fn from_tile_codes(codes: &[i32]) -> Result<Map, MapError> {
+        if codes.len() != Map::WIDTH * Map::HEIGHT {
+            return Err(MapError::InvalidLength {
+                expected: Map::WIDTH * Map::HEIGHT,
+                got: codes.len(),
+            });
+        }
+        let tiles = codes
+            .iter()
+            .map(|&code| Tile::from_tile_code(code))
+            .collect::<Result<Vec<Tile>, TileCreationError>>()?;
+        Ok(Map {
+            tiles,
+            ads: Vec::new(),
+            width: Map::WIDTH,
+            height: Map::HEIGHT,
+        })
+    }
+
+    //Magic and version, then the size, every tile code and the ads, all little-endian:
+    //  u32 width and height
+    //  i32 tile code * width * height
+    //  u32 ad count, then per ad a u8 size and i32 x and y
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Map::BINARY_MAGIC.len() + 1 + self.tiles.len() * 4);
+        bytes.extend_from_slice(Map::BINARY_MAGIC);
+        bytes.push(Map::BINARY_VERSION);
+        bytes.extend_from_slice(&(self.width as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.height as u32).to_le_bytes());
+        for code in self.to_tile_codes() {
+            bytes.extend_from_slice(&code.to_le_bytes());
+        }
+        bytes.extend_from_slice(&(self.ads.len() as u32).to_le_bytes());
+        for ad in &self.ads {
+            bytes.push(ad.size as u8);
+            bytes.extend_from_slice(&ad.x.to_le_bytes());
+            bytes.extend_from_slice(&ad.y.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Map, MapError> {
+        let mut input = bytes;
+        let header = take_bytes::<5>(&mut input).map_err(|_| MapError::InvalidHeader)?;
+        if &header[..4] != Map::BINARY_MAGIC {
+            return Err(MapError::InvalidHeader);
+        }
+        //Version 1 had no size, every map was the standard one
+        let (width, height) = match header[4] {
+            1 => (Map::WIDTH, Map::HEIGHT),
+            Map::BINARY_VERSION => (
+                u32::from_le_bytes(take_bytes(&mut input)?) as usize,
+                u32::from_le_bytes(take_bytes(&mut input)?) as usize,
+            ),
+            _ => return Err(MapError::InvalidHeader),
+        };
+        let count = width.checked_mul(height).ok_or(MapError::InvalidHeader)?;
+
+        let tiles = (0..count)
+            .map(|_| {
+                let code = i32::from_le_bytes(take_bytes(&mut input)?);
+                Ok(Tile::from_tile_code(code)?)
+            })
+            .collect::<Result<Vec<Tile>, MapError>>()?;
+        let mut map = Map {
+            tiles,
+            ads: Vec::new(),
+            width,
+            height,
+        };
+
+        let count = u32::from_le_bytes(take_bytes(&mut input)?);
+        for _ in 0..count {
+            let [size] = take_bytes(&mut input)?;
+            let size = AdSize::from_u8(size).ok_or(MapError::InvalidAdSize(size))?;
+            let x = i32::from_le_bytes(take_bytes(&mut input)?);
+            let y = i32::from_le_bytes(take_bytes(&mut input)?);
+            map.ads.push(Ad { size, x, y });
+        }
+        Ok(map)
+    }
+
     pub fn decode(s: String) -> Result<Map, MapError> {
+        Map::decode_reader(s.as_bytes())
+    }
+
+    pub fn decode_reader<R: BufRead>(reader: R) -> Result<Map, MapError> {
+        Map::decode_reader_with(reader, DecodeMode::Lenient)
+    }
+
+    pub fn decode_reader_with<R: BufRead>(reader: R, mode: DecodeMode) -> Result<Map, MapError> {
         let mut map = Map::new();
-        let mut iter = s.chars();
+        let mut chars = ReaderChars { reader };
 
         for y in 0..Map::HEIGHT {
+            let mut copy_row = false;
             for x in 0..Map::WIDTH {
-                if let Some(cur) = iter.next() {
-                    match cur {
-                        'A' | 'C' => {
-                            let a = iter.next().ok_or_else(|| MapError::UnexpectedEol)?;
-                            let b = iter.next().ok_or_else(|| MapError::UnexpectedEol)?;
-                            let a_code =
-                                Map::char_to_code(a).ok_or_else(|| MapError::Unexpected(a))?;
-                            let b_code =
-                                Map::char_to_code(b).ok_or_else(|| MapError::Unexpected(b))?;
-                            let cur =
-                                Map::char_to_code(cur).ok_or_else(|| MapError::Unexpected(b))?;
-                            let tile = Tile::from_i32s(cur, a_code, b_code, 0)?;
-                            map.set_tile(x, y, tile)?;
-                        }
-                        'B' => {
-                            let a = iter.next().ok_or_else(|| MapError::UnexpectedEol)?;
-                            let b = iter.next().ok_or_else(|| MapError::UnexpectedEol)?;
-                            let c = iter.next().ok_or_else(|| MapError::UnexpectedEol)?;
-                            let a_code =
-                                Map::char_to_code(a).ok_or_else(|| MapError::Unexpected(a))?;
-                            let b_code =
-                                Map::char_to_code(b).ok_or_else(|| MapError::Unexpected(b))?;
-                            let c_code =
-                                Map::char_to_code(c).ok_or_else(|| MapError::Unexpected(c))?;
-                            let cur =
-                                Map::char_to_code(cur).ok_or_else(|| MapError::Unexpected(b))?;
-                            let tile = Tile::from_i32s(cur, a_code, b_code, c_code)?;
-                            map.set_tile(x, y, tile)?;
-                        }
-                        'D' | 'E' | 'F' | 'G' | 'H' | 'I' => {
-                            let (offset_y, offset_x) = Map::get_offset(cur);
-                            let new_y = y
-                                .checked_sub(offset_y)
-                                .ok_or_else(|| MapError::OutOfBounds)?;
-                            let new_x = x
-                                .checked_sub(offset_x)
-                                .ok_or_else(|| MapError::OutOfBounds)?;
-                            map.set_tile(
-                                x,
-                                y,
-                                map.get_tile(new_x, new_y)
-                                    .ok_or_else(|| MapError::OutOfBounds)?,
-                            )?;
-                        }
-                        c => return Err(MapError::Unexpected(c)),
+                if copy_row {
+                    map.set_tile(x, y, map.tiles[Map::xy_to_index(x, y - 1)])?;
+                    continue;
+                }
+                let cur = match chars.next().transpose()? {
+                    Some(cur) => cur,
+                    None if mode == DecodeMode::Strict => {
+                        return Err(MapError::IncompleteGrid { x, y })
+                    }
+                    None => continue,
+                };
+                match cur {
+                    'A' | 'C' => {
+                        let a = chars.next().transpose()?.ok_or(MapError::UnexpectedEol)?;
+                        let b = chars.next().transpose()?.ok_or(MapError::UnexpectedEol)?;
+                        let a_code =
+                            Map::char_to_code(a).ok_or(MapError::UnexpectedAt { ch: a, x, y })?;
+                        let b_code =
+                            Map::char_to_code(b).ok_or(MapError::UnexpectedAt { ch: b, x, y })?;
+                        let special_code = Map::char_to_code(cur)
+                            .ok_or(MapError::UnexpectedAt { ch: cur, x, y })?;
+                        let tile = Tile::from_i32s(special_code, a_code, b_code, 0)?;
+                        map.set_tile(x, y, tile)?;
                     }
+                    'B' => {
+                        let a = chars.next().transpose()?.ok_or(MapError::UnexpectedEol)?;
+                        let b = chars.next().transpose()?.ok_or(MapError::UnexpectedEol)?;
+                        let c = chars.next().transpose()?.ok_or(MapError::UnexpectedEol)?;
+                        let a_code =
+                            Map::char_to_code(a).ok_or(MapError::UnexpectedAt { ch: a, x, y })?;
+                        let b_code =
+                            Map::char_to_code(b).ok_or(MapError::UnexpectedAt { ch: b, x, y })?;
+                        let c_code =
+                            Map::char_to_code(c).ok_or(MapError::UnexpectedAt { ch: c, x, y })?;
+                        let special_code = Map::char_to_code(cur)
+                            .ok_or(MapError::UnexpectedAt { ch: cur, x, y })?;
+                        let tile = Tile::from_i32s(special_code, a_code, b_code, c_code)?;
+                        map.set_tile(x, y, tile)?;
+                    }
+                    'D' | 'E' | 'F' | 'G' | 'H' | 'I' | 'J' | 'K' | 'L' => {
+                        let (offset_y, offset_x) = Map::get_offset(cur);
+                        let new_y = y.checked_sub(offset_y).ok_or(MapError::InvalidReference {
+                            ch: cur,
+                            x,
+                            y,
+                        })?;
+                        let new_x = x.checked_sub(offset_x).ok_or(MapError::InvalidReference {
+                            ch: cur,
+                            x,
+                            y,
+                        })?;
+                        map.set_tile(
+                            x,
+                            y,
+                            map.get_tile(new_x, new_y).ok_or(MapError::OutOfBounds)?,
+                        )?;
+                    }
+                    'M' => {
+                        let new_y =
+                            y.checked_sub(1)
+                                .ok_or(MapError::InvalidReference { ch: cur, x, y })?;
+                        map.set_tile(x, y, map.tiles[Map::xy_to_index(x, new_y)])?;
+                        copy_row = true;
+                    }
+                    c => return Err(MapError::UnexpectedAt { ch: c, x, y }),
                 }
             }
         }
 
+        if mode == DecodeMode::Strict && chars.next().transpose()?.is_some() {
+            return Err(MapError::TrailingData);
+        }
         Ok(map)
     }
 
@@ -225,6 +1022,10 @@ impl Map {
         }
     }
 
+    // Back-reference codes copy an already decoded tile at (y, x) tiles
+    // up and to the left. D-I are the original codes, J-L extend them to a
+    // distance of 3. 'M' is handled in `decode` and copies the rest of the
+    // current row from the row above. Only `Codec::Extended` writes J-M.
     fn get_offset(cur: char) -> (usize, usize) {
         match cur {
             'D' => (0, 1),
@@ -233,17 +1034,156 @@ impl Map {
             'G' => (0, 2),
             'H' => (2, 0),
             'I' => (2, 2),
+            'J' => (0, 3),
+            'K' => (3, 0),
+            'L' => (3, 3),
             _ => (0, 0),
         }
     }
+
+    pub fn encode(&self) -> Result<String, MapError> {
+        self.encode_with(Codec::Classic)
+    }
+
+    //Fails on maps of another size and on tiles the format can't express, a
+    //normal tile without a shape or a code past 'z'
+    pub fn encode_with(&self, codec: Codec) -> Result<String, MapError> {
+        //The track format has no size, decode always reads the standard one
+        if !self.is_standard_size() {
+            return Err(MapError::UnsupportedSize {
+                width: self.width,
+                height: self.height,
+            });
+        }
+        let codes: &[char] = match codec {
+            Codec::Classic => &['D', 'E', 'F', 'G', 'H', 'I'],
+            Codec::Extended => &['D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L'],
+        };
+        let mut output = String::new();
+        let mut previous = None;
+
+        for y in 0..Map::HEIGHT {
+            for x in 0..Map::WIDTH {
+                let tile = self.tiles[Map::xy_to_index(x, y)];
+
+                if codec == Codec::Extended
+                    && x + 1 < Map::WIDTH
+                    && y > 0
+                    && (x..Map::WIDTH).all(|i| {
+                        self.tiles[Map::xy_to_index(i, y)] == self.tiles[Map::xy_to_index(i, y - 1)]
+                    })
+                {
+                    output.push('M');
+                    previous = None;
+                    break;
+                }
+
+                let matches = |code: char| {
+                    let (offset_y, offset_x) = Map::get_offset(code);
+                    y.checked_sub(offset_y)
+                        .zip(x.checked_sub(offset_x))
+                        .is_some_and(|(new_y, new_x)| {
+                            self.tiles[Map::xy_to_index(new_x, new_y)] == tile
+                        })
+                };
+
+                // Repeating the previous code keeps runs intact for `compress`.
+                let code = previous
+                    .filter(|&code| matches(code))
+                    .or_else(|| codes.iter().copied().find(|&code| matches(code)));
+
+                match code {
+                    Some(code) => output.push(code),
+                    None => output.push_str(&Map::encode_tile(&tile)?),
+                }
+                previous = code;
+            }
+        }
+
+        Ok(output)
+    }
+
+    // Special tiles are written without a foreground, matching the 'C' arm
+    // in `decode`.
+    fn encode_tile(tile: &Tile) -> Result<String, MapError> {
+        match tile.special {
+            None => {
+                let shape = tile.shape.ok_or(MapError::MissingShape)?;
+                Ok([
+                    'B',
+                    Map::code_to_char(shape as i32)?,
+                    Map::code_to_char(tile.background as i32)?,
+                    Map::code_to_char(tile.foreground as i32)?,
+                ]
+                .iter()
+                .collect())
+            }
+            Some(special) => Ok([
+                'C',
+                Map::code_to_char(special as i32)?,
+                Map::code_to_char(tile.background as i32)?,
+            ]
+            .iter()
+            .collect()),
+        }
+    }
+
+    fn code_to_char(code: i32) -> Result<char, MapError> {
+        match code {
+            0..=25 => Ok((b'A' + code as u8) as char),
+            26..=51 => Ok((b'a' + (code - 26) as u8) as char),
+            _ => Err(MapError::InvalidCode(code)),
+        }
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+//Splits the next N bytes off the front of input
+fn take_bytes<const N: usize>(input: &mut &[u8]) -> Result<[u8; N], MapError> {
+    if input.len() < N {
+        return Err(MapError::UnexpectedEol);
+    }
+    let (head, rest) = input.split_at(N);
+    *input = rest;
+    Ok(head.try_into().unwrap())
+}
 
-    #[test]
-    fn test_char_to_code() {
+impl PackedMap {
+    pub fn from_map(map: &Map) -> PackedMap {
+        PackedMap {
+            tiles: map.tiles.iter().map(PackedTile::from_tile).collect(),
+            ads: map.ads.clone(),
+            width: map.width,
+            height: map.height,
+        }
+    }
+
+    pub fn unpack(&self) -> Result<Map, TileCreationError> {
+        let tiles = self
+            .tiles
+            .iter()
+            .map(PackedTile::unpack)
+            .collect::<Result<Vec<Tile>, TileCreationError>>()?;
+        Ok(Map {
+            tiles,
+            ads: self.ads.clone(),
+            width: self.width,
+            height: self.height,
+        })
+    }
+}
+
+impl fmt::Display for Map {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_ascii())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_char_to_code() {
         assert_eq!(Map::char_to_code('a'), Some(26));
         assert_eq!(Map::char_to_code('z'), Some(51));
         assert_eq!(Map::char_to_code('A'), Some(0));
@@ -251,6 +1191,984 @@ mod tests {
         assert_eq!(Map::char_to_code('!'), None);
     }
 
+    #[test]
+    fn test_decode_single_tile() {
+        let decode = |input: &str| Map::decode_reader(input.as_bytes());
+
+        let map = decode("BBAD").unwrap();
+        let circle = Tile::new(None, Some(Shape::BigCircle), Element::Grass, Element::Ice);
+        assert_eq!(map.get_tile(0, 0), Some(circle));
+        assert_eq!(map.get_tile(1, 0), Some(Tile::default()));
+
+        let map = decode("CBC").unwrap();
+        let hole = Tile::new(Some(Special::Hole), None, Element::Mud, Element::Grass);
+        assert_eq!(map.get_tile(0, 0), Some(hole));
+
+        //A is special code 0, which is neither a normal nor a special tile
+        assert!(matches!(
+            decode("ABA"),
+            Err(MapError::TileCreationError(
+                TileCreationError::InvalidSpecialParse(0)
+            ))
+        ));
+        assert!(matches!(
+            decode("BA!A"),
+            Err(MapError::UnexpectedAt {
+                ch: '!',
+                x: 0,
+                y: 0
+            })
+        ));
+    }
+
+    #[test]
+    fn test_to_track_string() {
+        let mut map = encode_test_map();
+        map.ads = Ad::from_string("A2309B2208C4019").unwrap();
+        let encoded = map.to_track_string().unwrap();
+        assert!(encoded.ends_with(",Ads:A2309B2208C4019"));
+        assert_eq!(Map::from_string(&encoded).unwrap(), map);
+    }
+
+    #[test]
+    fn test_from_string_lenient() {
+        let map = encode_test_map();
+        let encoded = Map::compress(&map.encode().unwrap());
+
+        let input = format!("{},Ads:A2309", encoded);
+        let (decoded, ads) = Map::from_string_lenient(&input).unwrap();
+        assert!(decoded.tiles_eq(&map));
+        assert_eq!(ads.unwrap().len(), 1);
+
+        let input = format!("{},Ads:A23Z9", encoded);
+        let (decoded, ads) = Map::from_string_lenient(&input).unwrap();
+        assert!(decoded.tiles_eq(&map));
+        assert!(ads.is_err());
+        assert!(Map::from_string(&input).is_err());
+
+        assert!(Map::from_string_lenient("BAQQ!,Ads:A2309").is_err());
+    }
+
+    #[test]
+    fn test_from_string_trailing_data() {
+        let map = encode_test_map();
+        let encoded = Map::compress(&map.encode().unwrap());
+
+        let input = format!("{},Ads:A2309B2208,Settings:fttf14,Ads:junk", encoded);
+        let decoded = Map::from_string(&input).unwrap();
+        assert!(decoded.tiles_eq(&map));
+        assert_eq!(decoded.ads, Ad::from_string("A2309B2208").unwrap());
+
+        let input = format!("{},Ads:,junk", encoded);
+        assert!(Map::from_string(&input).unwrap().ads.is_empty());
+    }
+
+    #[test]
+    fn test_decode_reader() {
+        let map = encode_test_map();
+        let encoded = map.encode().unwrap();
+        let decoded = Map::decode_reader(std::io::Cursor::new(encoded.as_bytes())).unwrap();
+        assert!(decoded.tiles_eq(&map));
+
+        //Tiny buffer so chars are read across refills
+        let reader = std::io::BufReader::with_capacity(2, encoded.as_bytes());
+        assert!(Map::decode_reader(reader).unwrap().tiles_eq(&map));
+
+        match Map::decode_reader(std::io::Cursor::new("BAQQ\u{e9}".as_bytes())) {
+            Err(MapError::UnexpectedAt { ch, x, y }) => assert_eq!((ch, x, y), ('\u{e9}', 1, 0)),
+            other => panic!("expected UnexpectedAt, got {:?}", other),
+        }
+        assert!(matches!(
+            Map::decode_reader(std::io::Cursor::new(&[b'B', 0xFF][..])),
+            Err(MapError::InvalidUtf8)
+        ));
+    }
+
+    #[test]
+    fn test_decode_mode() {
+        let map = encode_test_map();
+        let encoded = map.encode().unwrap();
+        let strict = |input: &str| Map::decode_reader_with(input.as_bytes(), DecodeMode::Strict);
+        assert_eq!(strict(&encoded).unwrap(), map);
+
+        //Short input leaves the rest of the grid default
+        let short = &encoded[..encoded.len() / 2];
+        assert!(Map::decode_reader(short.as_bytes()).is_ok());
+        assert!(matches!(
+            strict(short),
+            Err(MapError::IncompleteGrid { .. })
+        ));
+        assert!(matches!(
+            strict(""),
+            Err(MapError::IncompleteGrid { x: 0, y: 0 })
+        ));
+
+        let long = format!("{}BAAA", encoded);
+        assert_eq!(Map::decode_reader(long.as_bytes()).unwrap(), map);
+        assert!(matches!(strict(&long), Err(MapError::TrailingData)));
+    }
+
+    #[test]
+    fn test_decode_unexpected_at() {
+        let mut input = String::from("BAQQ");
+        input.push_str(&"D".repeat(Map::WIDTH - 1));
+        input.push_str("EDDBA!Q");
+
+        match Map::decode(input) {
+            Err(MapError::UnexpectedAt { ch, x, y }) => {
+                assert_eq!(ch, '!');
+                assert_eq!(x, 3);
+                assert_eq!(y, 1);
+            }
+            other => panic!("expected UnexpectedAt, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_decode_offsets_at_origin() {
+        for ch in ['D', 'E', 'F', 'G', 'H', 'I'] {
+            match Map::decode(ch.to_string()) {
+                Err(MapError::InvalidReference { ch: err_ch, x, y }) => {
+                    assert_eq!(err_ch, ch);
+                    assert_eq!((x, y), (0, 0));
+                }
+                other => panic!("expected InvalidReference for {}, got {:?}", ch, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_offsets_at_boundary() {
+        // Second tile of the first row: only D has something to point at.
+        assert!(Map::decode(String::from("BAQQD")).is_ok());
+        for ch in ['E', 'F', 'G', 'H', 'I'] {
+            let input = format!("BAQQ{}", ch);
+            assert!(matches!(
+                Map::decode(input),
+                Err(MapError::InvalidReference { x: 1, y: 0, .. })
+            ));
+        }
+
+        // First tile of the second row: only E has something to point at.
+        let row = format!("BAQQ{}", "D".repeat(Map::WIDTH - 1));
+        assert!(Map::decode(format!("{}E", row)).is_ok());
+        for ch in ['D', 'F', 'G', 'H', 'I'] {
+            let input = format!("{}{}", row, ch);
+            assert!(matches!(
+                Map::decode(input),
+                Err(MapError::InvalidReference { x: 0, y: 1, .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_decode_offsets_copy_neighbor() {
+        // The top-left 3x3 tiles get distinct foregrounds so a copied tile
+        // identifies its source.
+        let mut tokens = Vec::new();
+        for y in 0..3 {
+            for x in 0..Map::WIDTH {
+                if x < 3 {
+                    tokens.push(format!("BAA{}", (b'A' + (y * 3 + x) as u8) as char));
+                } else {
+                    tokens.push(String::from("D"));
+                }
+            }
+        }
+
+        let expected = [
+            ('D', 1, 0),
+            ('E', 0, 1),
+            ('F', 1, 1),
+            ('G', 2, 0),
+            ('H', 0, 2),
+            ('I', 2, 2),
+        ];
+        for (ch, dx, dy) in expected {
+            let mut input = tokens.clone();
+            input[Map::xy_to_index(2, 2)] = ch.to_string();
+            let map = Map::decode(input.concat()).unwrap();
+            assert_eq!(
+                map.get_tile(2, 2),
+                map.get_tile(2 - dx, 2 - dy),
+                "back-reference {}",
+                ch
+            );
+        }
+    }
+
+    fn encode_test_map() -> Map {
+        let mut map = Map::new();
+        let block = Tile::new(None, Some(Shape::Blank), Element::Block, Element::Block);
+        let hole = Tile::new(Some(Special::Hole), None, Element::Grass, Element::Grass);
+        let mud = Tile::new(None, Some(Shape::BigCircle), Element::Grass, Element::Mud);
+        for x in 0..Map::WIDTH {
+            map.set_tile(x, 0, block).unwrap();
+            map.set_tile(x, Map::HEIGHT - 1, block).unwrap();
+        }
+        for y in 3..Map::HEIGHT {
+            map.set_tile(3, y, mud).unwrap();
+        }
+        for y in (0..Map::HEIGHT).step_by(3) {
+            map.set_tile(10, y, hole).unwrap();
+        }
+        // Isolated pairs only reachable with the distance-3 codes.
+        map.set_tile(30, 5, mud).unwrap();
+        map.set_tile(33, 5, mud).unwrap();
+        map.set_tile(37, 5, hole).unwrap();
+        map.set_tile(40, 8, hole).unwrap();
+        map
+    }
+
+    #[test]
+    fn test_code_to_char() {
+        for c in ('A'..='Z').chain('a'..='z') {
+            assert_eq!(Map::code_to_char(Map::char_to_code(c).unwrap()).unwrap(), c);
+        }
+        assert!(matches!(
+            Map::code_to_char(52),
+            Err(MapError::InvalidCode(52))
+        ));
+        assert!(matches!(
+            Map::code_to_char(-1),
+            Err(MapError::InvalidCode(-1))
+        ));
+    }
+
+    #[test]
+    fn test_encode_missing_shape() {
+        let mut map = Map::new();
+        let tile = Tile::new(None, None, Element::Grass, Element::Grass);
+        map.set_tile(3, 4, tile).unwrap();
+        assert!(matches!(map.encode(), Err(MapError::MissingShape)));
+        assert!(map.to_track_string().is_err());
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let map = encode_test_map();
+        let encoded = map.encode_with(Codec::Extended).unwrap();
+        for code in ['J', 'K', 'L', 'M'] {
+            assert!(encoded.contains(code), "missing {} in {}", code, encoded);
+        }
+
+        let decoded = Map::decode(Map::decompress(&Map::compress(&encoded))).unwrap();
+        assert_eq!(decoded.tiles, map.tiles);
+    }
+
+    #[test]
+    fn test_encode_classic() {
+        let map = encode_test_map();
+        let encoded = map.encode().unwrap();
+        assert!(!encoded.contains(['J', 'K', 'L', 'M']), "{}", encoded);
+        assert!(encoded.len() > map.encode_with(Codec::Extended).unwrap().len());
+
+        let decoded = Map::decode(Map::decompress(&Map::compress(&encoded))).unwrap();
+        assert_eq!(decoded.tiles, map.tiles);
+        assert!(!map
+            .to_track_string()
+            .unwrap()
+            .contains(['J', 'K', 'L', 'M']));
+    }
+
+    #[test]
+    fn test_encode_round_trip_track() {
+        let track = crate::track::Track::from_filepath("testi.track").unwrap();
+        let decoded = Map::decode(track.map.encode().unwrap()).unwrap();
+        assert_eq!(decoded.tiles, track.map.tiles);
+    }
+
+    #[test]
+    fn test_decode_row_copy() {
+        let mut input = String::from("BAQQ");
+        input.push_str(&"D".repeat(Map::WIDTH - 1));
+        input.push_str("BABBM");
+        let map = Map::decode(input).unwrap();
+        assert_eq!(map.get_tile(0, 1).unwrap().foreground, Element::Dirt);
+        for x in 1..Map::WIDTH {
+            assert_eq!(map.get_tile(x, 1), map.get_tile(x, 0));
+        }
+    }
+
+    #[test]
+    fn test_to_ascii() {
+        let mut map = encode_test_map();
+        let start = Tile::new(
+            Some(Special::StartPosition),
+            None,
+            Element::Grass,
+            Element::Grass,
+        );
+        let water = Tile::new(None, Some(Shape::Blank), Element::Water, Element::Grass);
+        map.set_tile(1, 1, start).unwrap();
+        map.set_tile(2, 1, water).unwrap();
+
+        let ascii = map.to_ascii();
+        let lines: Vec<&str> = ascii.lines().collect();
+        assert_eq!(lines.len(), Map::HEIGHT);
+        assert!(lines.iter().all(|line| line.len() == Map::WIDTH));
+        assert_eq!(lines[0], format!("{}O{}", "#".repeat(10), "#".repeat(38)));
+        assert_eq!(lines[1], format!(".S~{}", ".".repeat(Map::WIDTH - 3)));
+        assert_eq!(&lines[3][..12], "...:......O.");
+        assert_eq!(map.to_string(), ascii);
+    }
+
+    #[test]
+    fn test_from_ascii_round_trip() {
+        let mut map = Map::new();
+        let elements = Element::all().iter().cycle();
+        for (tile, &element) in map.tiles.iter_mut().zip(elements) {
+            *tile = Tile::new(None, Some(Shape::Blank), element, Element::Grass);
+        }
+        for (x, &special) in Special::all().iter().enumerate() {
+            let tile = Tile::new(Some(special), None, Element::Grass, Element::Grass);
+            map.set_tile(x, 10, tile).unwrap();
+        }
+
+        let ascii = map.to_ascii();
+        let parsed = Map::from_ascii(&ascii).unwrap();
+        assert_eq!(parsed.tiles, map.tiles);
+        assert_eq!(parsed.to_ascii(), ascii);
+    }
+
+    #[test]
+    fn test_from_ascii_errors() {
+        let row = ".".repeat(Map::WIDTH);
+        let short = vec![row.as_str(); Map::HEIGHT - 1].join("\n");
+        assert!(matches!(
+            Map::from_ascii(&short),
+            Err(MapError::UnexpectedEol)
+        ));
+
+        let mut lines = vec![row.clone(); Map::HEIGHT];
+        lines[2].replace_range(5..6, "?");
+        assert!(matches!(
+            Map::from_ascii(&lines.join("\n")),
+            Err(MapError::UnexpectedAt {
+                ch: '?',
+                x: 5,
+                y: 2
+            })
+        ));
+
+        let mut lines = vec![row.clone(); Map::HEIGHT];
+        lines[0].push('.');
+        assert!(matches!(
+            Map::from_ascii(&lines.join("\n")),
+            Err(MapError::OutOfBounds)
+        ));
+
+        let mut lines = vec![row.clone(); Map::HEIGHT + 1];
+        assert!(matches!(
+            Map::from_ascii(&lines.join("\n")),
+            Err(MapError::OutOfBounds)
+        ));
+        lines[Map::HEIGHT] = String::new();
+        lines.push(String::new());
+        assert!(Map::from_ascii(&lines.join("\n")).is_ok());
+    }
+
+    #[test]
+    fn test_path_exists() {
+        let mut map = Map::new();
+        let block = Tile::new(None, Some(Shape::Blank), Element::Block, Element::Block);
+        assert!(map.path_exists((0, 0), (48, 24)));
+
+        for y in 0..Map::HEIGHT {
+            map.set_tile(20, y, block).unwrap();
+        }
+        assert!(!map.path_exists((0, 0), (48, 24)));
+        assert!(map.path_exists((0, 0), (19, 24)));
+    }
+
+    #[test]
+    fn test_random() {
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+
+        let map = Map::random(&mut StdRng::seed_from_u64(7));
+        let find = |special: Special| -> Vec<usize> {
+            (0..map.tiles.len())
+                .filter(|&i| map.tiles[i].special == Some(special))
+                .collect()
+        };
+        let starts = find(Special::StartPosition);
+        let holes = find(Special::Hole);
+        assert_eq!(starts.len(), 1);
+        assert_eq!(holes.len(), 1);
+        assert!(map.path_exists(Map::index_to_xy(starts[0]), Map::index_to_xy(holes[0])));
+
+        let again = Map::random(&mut StdRng::seed_from_u64(7));
+        assert_eq!(again.tiles, map.tiles);
+    }
+
+    #[test]
+    fn test_resize() {
+        let block = Tile::default().with_background(Element::Block);
+        let ice = Tile::default().with_background(Element::Ice);
+        let mut map = Map::new();
+        map.set_tile(0, 0, block).unwrap();
+        map.set_tile(Map::WIDTH - 1, 0, ice).unwrap();
+        map.set_tile(0, Map::HEIGHT - 1, ice).unwrap();
+        map.set_tile(Map::WIDTH - 1, Map::HEIGHT - 1, block)
+            .unwrap();
+
+        map.resize(60, 30);
+        assert_eq!((map.width(), map.height()), (60, 30));
+        assert_eq!(map.len(), 60 * 30);
+        assert_eq!(map.get_tile(0, 0), Some(block));
+        assert_eq!(map.get_tile(Map::WIDTH - 1, 0), Some(ice));
+        assert_eq!(map.get_tile(0, Map::HEIGHT - 1), Some(ice));
+        assert_eq!(map.get_tile(Map::WIDTH - 1, Map::HEIGHT - 1), Some(block));
+        for (x, y) in [(Map::WIDTH, 0), (59, 29), (0, Map::HEIGHT)] {
+            assert_eq!(map.get_tile(x, y), Some(Tile::default()));
+            assert_eq!(map.get_tile(x, y).unwrap().background, Element::Grass);
+        }
+        assert_eq!(map.get_tile(60, 0), None);
+        assert_eq!(
+            map.iter_tiles().last().map(|(_, x, y)| (x, y)),
+            Some((59, 29))
+        );
+
+        map.resize(10, 5);
+        assert_eq!(map.len(), 50);
+        assert_eq!(map.get_tile(0, 0), Some(block));
+        assert_eq!(map.get_tile(Map::WIDTH - 1, 0), None);
+        assert!(map.set_tile(10, 0, ice).is_err());
+        assert_eq!(map.to_ascii().lines().count(), 5);
+    }
+
+    #[test]
+    fn test_resized_map() {
+        let start = |special| Tile::new(Some(special), None, Element::Grass, Element::Grass);
+        let ice = Tile::default().with_background(Element::Ice);
+        let mut map = Map::new();
+        map.resize(60, 30);
+        map.set_tile(55, 28, start(Special::Hole)).unwrap();
+        map.set_tile(52, 3, start(Special::StartPosition)).unwrap();
+        map.set_tile(58, 1, ice).unwrap();
+        assert_eq!(map.find_all_special(Special::Hole), vec![(55, 28)]);
+        assert_eq!(map.find_all_element(Element::Ice), vec![(58, 1)]);
+        assert_eq!(
+            map.start_positions(),
+            vec![(Special::StartPosition, (52, 3))]
+        );
+        assert!(map.path_exists((52, 3), (55, 28)));
+        assert!(!map.path_exists((52, 3), (60, 28)));
+        assert_eq!(
+            map.validate(),
+            vec![MapValidationIssue::Size {
+                width: 60,
+                height: 30
+            }]
+        );
+        assert!(matches!(
+            map.encode(),
+            Err(MapError::UnsupportedSize {
+                width: 60,
+                height: 30
+            })
+        ));
+        assert!(map.to_track_string().is_err());
+
+        map.resize(10, 5);
+        map.set_tile(1, 2, ice).unwrap();
+        let flipped = map.flip_horizontal();
+        assert_eq!((flipped.width(), flipped.height()), (10, 5));
+        assert_eq!(flipped.find_all_element(Element::Ice), vec![(8, 2)]);
+        let flipped = map.flip_vertical();
+        assert_eq!((flipped.width(), flipped.height()), (10, 5));
+        assert_eq!(flipped.find_all_element(Element::Ice), vec![(1, 2)]);
+        map.set_tile(1, 2, Tile::default()).unwrap();
+        map.set_tile(1, 0, ice).unwrap();
+        assert_eq!(
+            map.flip_vertical().find_all_element(Element::Ice),
+            vec![(1, 4)]
+        );
+    }
+
+    #[test]
+    fn test_for_each_tile_mut() {
+        let speed = |element| Tile::new(None, Some(Shape::Blank), element, Element::Grass);
+        let mut map = Map::new();
+        map.set_tile(2, 3, speed(Element::SpeedE)).unwrap();
+        map.set_tile(40, 20, speed(Element::SpeedE)).unwrap();
+        map.set_tile(5, 5, speed(Element::SpeedN)).unwrap();
+        let original = map.clone();
+
+        let mut visited = 0;
+        map.for_each_tile_mut(|tile, x, y| {
+            visited += 1;
+            assert_eq!(Some(*tile), original.get_tile(x, y));
+            if tile.background == Element::SpeedE {
+                tile.background = Element::SpeedW;
+            }
+        });
+        assert_eq!(visited, Map::WIDTH * Map::HEIGHT);
+        assert_eq!(
+            map.find_all_element(Element::SpeedW),
+            vec![(2, 3), (40, 20)]
+        );
+        assert!(map.find_all_element(Element::SpeedE).is_empty());
+        assert_eq!(map.get_tile(5, 5), Some(speed(Element::SpeedN)));
+        assert_eq!(map.solid_tile_count(), original.solid_tile_count());
+    }
+
+    #[test]
+    fn test_flip() {
+        let mut map = Map::new();
+        let speed = Tile::new(None, Some(Shape::Blank), Element::SpeedNE, Element::Grass);
+        let corner = Tile::new(
+            None,
+            Some(Shape::TriangleSE),
+            Element::Grass,
+            Element::Block,
+        );
+        map.set_tile(0, 0, speed).unwrap();
+        map.set_tile(1, 0, corner).unwrap();
+
+        let flipped = map.flip_horizontal();
+        assert_eq!(
+            flipped.get_tile(Map::WIDTH - 1, 0).unwrap().background,
+            Element::SpeedNW
+        );
+        assert_eq!(
+            flipped.get_tile(Map::WIDTH - 2, 0).unwrap().shape,
+            Some(Shape::TriangleSW)
+        );
+        assert_eq!(flipped.flip_horizontal().tiles, map.tiles);
+
+        let flipped = map.flip_vertical();
+        assert_eq!(
+            flipped.get_tile(0, Map::HEIGHT - 1).unwrap().background,
+            Element::SpeedSE
+        );
+        assert_eq!(
+            flipped.get_tile(1, Map::HEIGHT - 1).unwrap().shape,
+            Some(Shape::TriangleNE)
+        );
+        assert_eq!(flipped.flip_vertical().tiles, map.tiles);
+    }
+
+    #[test]
+    fn test_random_symmetric() {
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+
+        let mut rng = StdRng::seed_from_u64(11);
+        let map = Map::random_symmetric(&mut rng, SymmetryAxis::Horizontal);
+        assert_eq!(map.flip_horizontal().tiles, map.tiles);
+        let map = Map::random_symmetric(&mut rng, SymmetryAxis::Vertical);
+        assert_eq!(map.flip_vertical().tiles, map.tiles);
+
+        let count = |special: Special| {
+            map.tiles
+                .iter()
+                .filter(|tile| tile.special == Some(special))
+                .count()
+        };
+        assert_eq!(count(Special::StartPosition), 1);
+        assert_eq!(count(Special::Hole), 1);
+    }
+
+    #[test]
+    fn test_find_all() {
+        let mut map = Map::new();
+        let mine = Tile::new(Some(Special::Mine), None, Element::Grass, Element::Grass);
+        let exit = Tile::new(
+            Some(Special::RedTeleportExit),
+            None,
+            Element::Grass,
+            Element::Grass,
+        );
+        let ice = Tile::new(None, Some(Shape::Blank), Element::Ice, Element::Grass);
+        let circle = Tile::new(None, Some(Shape::BigCircle), Element::Grass, Element::Ice);
+        map.set_tile(1, 1, mine).unwrap();
+        map.set_tile(30, 5, mine).unwrap();
+        map.set_tile(2, 20, mine).unwrap();
+        map.set_tile(7, 7, exit).unwrap();
+        map.set_tile(4, 0, ice).unwrap();
+        map.set_tile(5, 0, circle).unwrap();
+
+        assert_eq!(
+            map.find_all_special(Special::Mine),
+            vec![(1, 1), (30, 5), (2, 20)]
+        );
+        assert!(map.find_all_special(Special::BigMine).is_empty());
+        assert_eq!(map.find_all_element(Element::Ice), vec![(4, 0), (5, 0)]);
+        assert_eq!(
+            map.find_teleport_exits(Special::RedTeleportStart),
+            vec![(7, 7)]
+        );
+        assert!(map.find_teleport_exits(Special::Mine).is_empty());
+    }
+
+    #[test]
+    fn test_in_bounds() {
+        assert!(Map::in_bounds(0, 0));
+        assert!(Map::in_bounds(Map::WIDTH - 1, Map::HEIGHT - 1));
+        assert!(!Map::in_bounds(Map::WIDTH, 0));
+        assert!(!Map::in_bounds(0, Map::HEIGHT));
+
+        let mut map = Map::new();
+        assert_eq!(map.len(), Map::WIDTH * Map::HEIGHT);
+        assert!(!map.is_empty());
+        assert!(Map::default().is_empty());
+
+        assert!(map.get_tile(Map::WIDTH - 1, Map::HEIGHT - 1).is_some());
+        assert!(map.get_tile(Map::WIDTH, Map::HEIGHT - 1).is_none());
+        assert!(matches!(
+            map.set_tile(0, Map::HEIGHT, Tile::default()),
+            Err(MapError::OutOfBounds)
+        ));
+
+        map.get_tile_mut(Map::WIDTH - 1, 0).unwrap().background = Element::Ice;
+        assert_eq!(
+            map.get_tile(Map::WIDTH - 1, 0).unwrap().background,
+            Element::Ice
+        );
+        assert!(map.get_tile_mut(0, Map::HEIGHT).is_none());
+    }
+
+    #[test]
+    fn test_ad_digits() {
+        let two = Ad::from_string_with_digits("A2309C4019", 2).unwrap();
+        let three = Ad::from_string_with_digits("A023009C040019", 3).unwrap();
+        assert_eq!(two.len(), 2);
+        assert_eq!(three.len(), 2);
+        for (a, b) in two.iter().zip(three.iter()) {
+            assert_eq!(a.size, b.size);
+            assert_eq!((a.x, a.y), (b.x, b.y));
+        }
+        assert_eq!((three[1].x, three[1].y), (40, 19));
+
+        assert!(matches!(
+            Ad::from_string_with_digits("A02300", 3),
+            Err(MapError::UnexpectedEol)
+        ));
+
+        //Out of range ads still load and are left to validate_ads
+        let mut map = Map::new();
+        map.ads = Ad::from_string_with_digits("A100009", 3).unwrap();
+        assert_eq!((map.ads[0].x, map.ads[0].y), (100, 9));
+        assert_eq!(map.validate_ads(), vec![AdIssue::OutOfBounds(0)]);
+        map.resize(120, Map::HEIGHT);
+        assert!(map.validate_ads().is_empty());
+
+        let mut map = Map::new();
+        map.ads = Ad::from_string("A0925").unwrap();
+        let map = Map::from_string(&map.to_track_string().unwrap()).unwrap();
+        assert_eq!((map.ads[0].x, map.ads[0].y), (9, 25));
+        assert_eq!(map.validate_ads(), vec![AdIssue::OutOfBounds(0)]);
+    }
+
+    #[test]
+    fn test_indexing() {
+        let mut map = Map::new();
+        for y in 0..Map::HEIGHT {
+            for x in 0..Map::WIDTH {
+                let index = Map::xy_to_index(x, y);
+                assert_eq!(Map::index_to_xy(index), (x, y));
+                let element = Element::all()[index % Element::all().len()];
+                map.set_tile(x, y, Tile::new(None, Some(Shape::Blank), element, element))
+                    .unwrap();
+            }
+        }
+        for (i, tile) in map.tiles.iter().enumerate() {
+            let (x, y) = Map::index_to_xy(i);
+            assert_eq!(map.get_tile(x, y), Some(*tile));
+            assert_eq!(tile.background, Element::all()[i % Element::all().len()]);
+        }
+
+        // A default map has no tiles, lookups miss instead of panicking
+        let mut empty = Map::default();
+        assert_eq!(empty.get_tile(0, 0), None);
+        assert!(empty.set_tile(0, 0, Tile::default()).is_err());
+    }
+
+    #[test]
+    fn test_tile_at_pixel() {
+        let mut map = Map::new();
+        let hole = Tile::new(Some(Special::Hole), None, Element::Grass, Element::Grass);
+        map.set_tile(0, 0, hole).unwrap();
+        map.set_tile(Map::WIDTH - 1, 1, hole).unwrap();
+
+        assert_eq!(map.tile_at_pixel(Vector2D::new(14.9, 0.0)), Some(hole));
+        assert_eq!(
+            map.tile_at_pixel(Vector2D::new(15.0, 0.0)),
+            Some(Tile::default())
+        );
+        let last_x = (Map::WIDTH * Map::TILESIZE) as f32 - 0.5;
+        assert_eq!(map.tile_at_pixel(Vector2D::new(last_x, 20.0)), Some(hole));
+
+        assert_eq!(map.tile_at_pixel(Vector2D::new(last_x + 1.0, 20.0)), None);
+        assert_eq!(map.tile_at_pixel(Vector2D::new(-0.5, 7.0)), None);
+        assert_eq!(map.tile_at_pixel(Vector2D::new(7.0, 1000.0)), None);
+    }
+
+    #[test]
+    fn test_set_start_and_hole() {
+        let mut map = Map::new();
+        map.set_tile(3, 3, Tile::default().with_background(Element::Ice))
+            .unwrap();
+        map.set_start(2, 2).unwrap();
+        map.set_start(3, 3).unwrap();
+        map.set_hole(10, 10).unwrap();
+        map.set_hole(11, 10).unwrap();
+
+        assert_eq!(map.find_all_special(Special::StartPosition), vec![(3, 3)]);
+        assert_eq!(map.find_all_special(Special::Hole), vec![(11, 10)]);
+        assert_eq!(map.get_tile(2, 2), Some(Tile::default()));
+        assert_eq!(map.get_tile(3, 3).unwrap().background, Element::Ice);
+
+        //A start placed on the hole replaces it, a failed move changes nothing
+        map.set_start(11, 10).unwrap();
+        assert_eq!(map.find_all_special(Special::Hole), vec![]);
+        assert!(map.set_hole(Map::WIDTH, 0).is_err());
+        assert_eq!(map.find_all_special(Special::StartPosition), vec![(11, 10)]);
+    }
+
+    #[test]
+    fn test_packed_map() {
+        let mut map = encode_test_map();
+        map.ads = Ad::from_string("A2309").unwrap();
+        let packed = PackedMap::from_map(&map);
+        assert_eq!(
+            std::mem::size_of_val(packed.tiles.as_slice()),
+            Map::WIDTH * Map::HEIGHT * 4
+        );
+
+        let unpacked = packed.unpack().unwrap();
+        assert_eq!(unpacked.tiles, map.tiles);
+        assert_eq!(unpacked.ads.len(), 1);
+    }
+
+    #[test]
+    fn test_tile_codes() {
+        let map = encode_test_map();
+        let codes = map.to_tile_codes();
+        assert_eq!(codes.len(), Map::WIDTH * Map::HEIGHT);
+        assert_eq!(codes[0], map.tiles[0].to_tile_code());
+        assert_eq!(Map::from_tile_codes(&codes).unwrap(), map);
+
+        assert!(matches!(
+            Map::from_tile_codes(&codes[1..]),
+            Err(MapError::InvalidLength { got, .. }) if got == codes.len() - 1
+        ));
+        let mut invalid = codes;
+        invalid[3] = 99 << 24;
+        assert!(matches!(
+            Map::from_tile_codes(&invalid),
+            Err(MapError::TileCreationError(_))
+        ));
+    }
+
+    #[test]
+    fn test_bytes() {
+        let mut map = encode_test_map();
+        map.ads = Ad::from_string("A2309C0102").unwrap();
+        let bytes = map.to_bytes();
+        assert_eq!(&bytes[..4], Map::BINARY_MAGIC);
+        assert_eq!(
+            bytes.len(),
+            5 + 8 + Map::WIDTH * Map::HEIGHT * 4 + 4 + 2 * 9
+        );
+        assert_eq!(Map::from_bytes(&bytes).unwrap(), map);
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        assert!(matches!(
+            Map::from_bytes(&bad_magic),
+            Err(MapError::InvalidHeader)
+        ));
+        let mut bad_version = bytes.clone();
+        bad_version[4] = Map::BINARY_VERSION + 1;
+        assert!(matches!(
+            Map::from_bytes(&bad_version),
+            Err(MapError::InvalidHeader)
+        ));
+        assert!(matches!(
+            Map::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(MapError::UnexpectedEol)
+        ));
+
+        let mut version_1 = bytes[..5].to_vec();
+        version_1[4] = 1;
+        version_1.extend_from_slice(&bytes[13..]);
+        assert_eq!(Map::from_bytes(&version_1).unwrap(), map);
+
+        map.resize(10, 5);
+        assert_eq!(Map::from_bytes(&map.to_bytes()).unwrap(), map);
+    }
+
+    #[test]
+    fn test_validate_team_starts() {
+        let start = |special| Tile::new(Some(special), None, Element::Grass, Element::Grass);
+        let settings: Settings = "tfff12".parse().unwrap();
+        let mut map = Map::new();
+        map.set_tile(1, 1, start(Special::StartPosition)).unwrap();
+        assert_eq!(map.validate_team_starts(&settings), Ok(()));
+
+        map.set_tile(2, 1, start(Special::StartPositionBlue))
+            .unwrap();
+        map.set_tile(3, 1, start(Special::StartPositionRed))
+            .unwrap();
+        assert_eq!(map.start_positions().len(), 3);
+        assert_eq!(map.validate_team_starts(&settings), Ok(()));
+
+        map.set_tile(4, 1, start(Special::StartPositionGreen))
+            .unwrap();
+        assert_eq!(
+            map.validate_team_starts(&settings),
+            Err(MapValidationIssue::TeamStartCount {
+                count: 3,
+                min: 1,
+                max: 2
+            })
+        );
+
+        map.set_tile(4, 1, start(Special::StartPositionBlue))
+            .unwrap();
+        assert_eq!(
+            map.validate_team_starts(&settings),
+            Err(MapValidationIssue::DuplicateTeamStart(TeamColor::Blue))
+        );
+    }
+
+    #[test]
+    fn test_open_ratio() {
+        let mut map = Map::new();
+        let open = map.open_ratio();
+        assert_eq!(map.solid_tile_count(), 0);
+        assert_eq!(open, 1.0);
+
+        let block = Tile::new(None, Some(Shape::Blank), Element::Block, Element::Block);
+        for y in 0..Map::HEIGHT {
+            for x in 0..Map::WIDTH / 2 {
+                map.set_tile(x, y, block).unwrap();
+            }
+        }
+        assert_eq!(map.solid_tile_count(), Map::WIDTH / 2 * Map::HEIGHT);
+        assert!((map.open_ratio() - 0.5).abs() < 0.02);
+
+        //A blank shape hides the foreground, any other shape shows it
+        let hidden = Tile::new(None, Some(Shape::Blank), Element::Grass, Element::Block);
+        let shown = hidden.with_shape(Shape::BigCircle);
+        let breakable = Tile::default().with_special(Special::FullBreakable);
+        map = Map::new();
+        map.set_tile(0, 0, hidden).unwrap();
+        map.set_tile(1, 0, shown).unwrap();
+        map.set_tile(2, 0, breakable).unwrap();
+        assert_eq!(map.solid_tile_count(), 2);
+
+        assert_eq!(Map::default().open_ratio(), 0.0);
+    }
+
+    #[test]
+    fn test_iter_tiles() {
+        let map = encode_test_map();
+        for (tile, x, y) in map.iter_tiles() {
+            assert_eq!(Some(*tile), map.get_tile(x, y));
+        }
+        let (_, x, y) = map.iter_tiles().nth(Map::WIDTH + 2).unwrap();
+        assert_eq!((x, y), (2, 1));
+    }
+
+    #[test]
+    fn test_swap_tiles() {
+        let mut map = Map::new();
+        let hole = Tile::new(Some(Special::Hole), None, Element::Grass, Element::Grass);
+        let grass = Tile::new(None, Some(Shape::Blank), Element::Grass, Element::Grass);
+        map.set_tile(3, 4, hole).unwrap();
+        map.set_tile(20, 10, grass).unwrap();
+
+        map.swap_tiles((3, 4), (20, 10)).unwrap();
+        assert_eq!(map.get_tile(3, 4), Some(grass));
+        assert_eq!(map.get_tile(20, 10), Some(hole));
+
+        map.swap_tiles((20, 10), (20, 10)).unwrap();
+        assert_eq!(map.get_tile(20, 10), Some(hole));
+
+        assert!(matches!(
+            map.swap_tiles((3, 4), (Map::WIDTH, 0)),
+            Err(MapError::OutOfBounds)
+        ));
+        assert!(matches!(
+            map.swap_tiles((0, Map::HEIGHT), (3, 4)),
+            Err(MapError::OutOfBounds)
+        ));
+        assert_eq!(map.get_tile(3, 4), Some(grass));
+    }
+
+    #[test]
+    fn test_tiles_eq() {
+        let mut map = encode_test_map();
+        let mut other = encode_test_map();
+        map.ads = Ad::from_string("A2309").unwrap();
+        other.ads = Ad::from_string("B2208").unwrap();
+        assert!(map.tiles_eq(&other));
+        assert_ne!(map, other);
+
+        other.ads = map.ads.clone();
+        assert_eq!(map, other);
+
+        other.set_tile(0, 0, Tile::default()).unwrap();
+        assert!(!map.tiles_eq(&other));
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut map = Map::new();
+        assert_eq!(
+            map.validate(),
+            vec![
+                MapValidationIssue::MissingStart,
+                MapValidationIssue::MissingHole
+            ]
+        );
+
+        let special = |special| Tile::new(Some(special), None, Element::Grass, Element::Grass);
+        map.set_tile(1, 1, special(Special::StartPosition)).unwrap();
+        map.set_tile(9, 9, special(Special::Hole)).unwrap();
+        assert!(map.validate().is_empty());
+
+        map.tiles.clear();
+        assert_eq!(map.validate(), vec![MapValidationIssue::TileCount(0)]);
+        assert_eq!(
+            Map::default().validate(),
+            vec![MapValidationIssue::Size {
+                width: 0,
+                height: 0
+            }]
+        );
+    }
+
+    #[test]
+    fn test_validate_ads() {
+        let mut map = Map::new();
+        map.ads = Ad::from_string("A0101B1010C3015").unwrap();
+        assert!(map.validate_ads().is_empty());
+
+        map.ads = Ad::from_string("A4700").unwrap();
+        assert_eq!(map.validate_ads(), vec![AdIssue::OutOfBounds(0)]);
+
+        map.ads = Ad::from_string("D0000").unwrap();
+        assert!(map.validate_ads().is_empty());
+
+        map.ads = Ad::from_string("A0101B0808A0202").unwrap();
+        assert_eq!(map.validate_ads(), vec![AdIssue::Overlap(0, 2)]);
+
+        //Sizes don't matter, a small ad inside a large one overlaps it
+        map.ads = Ad::from_string("C1010A1211B3000").unwrap();
+        assert_eq!(map.validate_ads(), vec![AdIssue::Overlap(0, 1)]);
+        map.ads = Ad::from_string("A2309B2208").unwrap();
+        assert_eq!(map.validate_ads(), vec![AdIssue::Overlap(0, 1)]);
+
+        // Touching edges do not overlap
+        map.ads = Ad::from_string("A0101A0401A0103").unwrap();
+        assert!(map.validate_ads().is_empty());
+    }
+
     #[test]
     fn test_from_string() {
         let input = "A2309B2208C4019";
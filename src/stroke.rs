@@ -1,6 +1,13 @@
 use crate::vector2d::Vector2D;
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Debug, PartialEq, Error)]
+#[error("Unknown shooting mode {0}")]
+pub struct ParseShootingModeError(String);
 
 #[derive(Debug, PartialEq, FromPrimitive, Copy, Clone)]
 pub enum ShootingMode {
@@ -15,41 +22,183 @@ impl ShootingMode {
         let i = (self as i32 + 1) % 4;
         FromPrimitive::from_i32(i).unwrap_or(ShootingMode::Normal)
     }
+
+    //Rows of the 2x2 matrix that turns the stroke power into the shot direction
+    pub fn matrix(&self) -> [[f32; 2]; 2] {
+        match self {
+            ShootingMode::Normal => [[1.0, 0.0], [0.0, 1.0]],
+            ShootingMode::Reverse => [[-1.0, 0.0], [0.0, -1.0]],
+            ShootingMode::Right => [[0.0, 1.0], [-1.0, 0.0]],
+            ShootingMode::Left => [[0.0, -1.0], [1.0, 0.0]],
+        }
+    }
+
+    pub fn transform(&self, power: Vector2D<f32>) -> Vector2D<f32> {
+        let [[a, b], [c, d]] = self.matrix();
+        Vector2D::new(a * power.x + b * power.y, c * power.x + d * power.y)
+    }
+}
+
+impl fmt::Display for ShootingMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ShootingMode::Normal => "normal",
+            ShootingMode::Reverse => "reverse",
+            ShootingMode::Right => "right",
+            ShootingMode::Left => "left",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for ShootingMode {
+    type Err = ParseShootingModeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "normal" => Ok(ShootingMode::Normal),
+            "reverse" => Ok(ShootingMode::Reverse),
+            "right" => Ok(ShootingMode::Right),
+            "left" => Ok(ShootingMode::Left),
+            _ => Err(ParseShootingModeError(s.to_string())),
+        }
+    }
+}
+
+//Power is (distance - dead_zone) / scale_divisor clamped to min_scale..=max_scale
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct StrokeConfig {
+    pub dead_zone: f32,
+    pub scale_divisor: f32,
+    pub min_scale: f32,
+    pub max_scale: f32,
 }
 
-struct Stroke {}
+impl Default for StrokeConfig {
+    fn default() -> Self {
+        Self {
+            dead_zone: 5.0,
+            scale_divisor: 30.0,
+            min_scale: 0.075,
+            max_scale: 6.5,
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub struct PhysicsConfig {
+    pub stroke: StrokeConfig,
+    //Lets angled bounces spin the ball, see Ball::bounce
+    pub enable_spin: bool,
+}
+
+//What an aim arrow needs, strength01 is the power as a fraction of max_scale
+#[derive(Debug, Copy, Clone)]
+pub struct PowerIndicator {
+    pub direction: Vector2D<f32>,
+    pub strength01: f32,
+}
+
+/// A shot aimed from `origin`.
+///
+/// ```
+/// use minigolf::stroke::{PhysicsConfig, ShootingMode, Stroke};
+/// use minigolf::vector2d::Vector2D;
+///
+/// let stroke = Stroke::new(Vector2D::new(52.5, 187.5), PhysicsConfig::default());
+/// let speed = stroke.speed(Vector2D::new(89.0, 327.0), ShootingMode::Normal);
+/// assert!(speed.y > speed.x);
+/// ```
+#[derive(Debug, Copy, Clone)]
+pub struct Stroke {
+    pub origin: Vector2D<f32>,
+    pub config: PhysicsConfig,
+}
 
 impl Stroke {
+    pub fn new(origin: Vector2D<f32>, config: PhysicsConfig) -> Self {
+        Self { origin, config }
+    }
+
+    pub fn power(&self, mouse_position: Vector2D<f32>) -> Vector2D<f32> {
+        Self::stroke_power(self.origin, mouse_position, &self.config.stroke)
+    }
+
+    pub fn speed(&self, mouse_coords: Vector2D<f32>, mode: ShootingMode) -> Vector2D<f32> {
+        Self::stroke_speed(self.origin, mouse_coords, mode, &self.config.stroke)
+    }
+
     pub fn calculate_stroke_power(
         origin: Vector2D<f32>,
         mouse_position: Vector2D<f32>,
+    ) -> Vector2D<f32> {
+        Self::stroke_power(origin, mouse_position, &StrokeConfig::default())
+    }
+
+    pub fn calculate_stroke_power_with(
+        origin: Vector2D<f32>,
+        mouse_position: Vector2D<f32>,
+        config: &StrokeConfig,
+    ) -> Vector2D<f32> {
+        Self::stroke_power(origin, mouse_position, config)
+    }
+
+    pub fn power_indicator(origin: Vector2D<f32>, mouse_position: Vector2D<f32>) -> PowerIndicator {
+        let power = Self::calculate_stroke_power(origin, mouse_position);
+        PowerIndicator {
+            direction: power.try_normalize().unwrap_or(Vector2D::zero()),
+            strength01: (power.length() / StrokeConfig::default().max_scale).clamp(0.0, 1.0),
+        }
+    }
+
+    pub fn calculate_speed(
+        origin: Vector2D<f32>,
+        mouse_coords: Vector2D<f32>,
+        mode: ShootingMode,
+    ) -> Vector2D<f32> {
+        Self::stroke_speed(origin, mouse_coords, mode, &StrokeConfig::default())
+    }
+
+    //Snaps the angle of power to the nearest of increments evenly spaced directions
+    pub fn snap_direction(power: Vector2D<f32>, increments: u32) -> Vector2D<f32> {
+        if increments == 0 {
+            return power;
+        }
+        let length = power.length();
+        let step = std::f32::consts::TAU / increments as f32;
+        let angle = (power.y.atan2(power.x) / step).round() * step;
+        Vector2D::new(angle.cos() * length, angle.sin() * length)
+    }
+
+    fn stroke_power(
+        origin: Vector2D<f32>,
+        mouse_position: Vector2D<f32>,
+        config: &StrokeConfig,
     ) -> Vector2D<f32> {
         let displacement = mouse_position - origin;
         let distance = displacement.length();
-        let mut scale = (distance - 5.0) / 30.0;
+        let mut scale = (distance - config.dead_zone) / config.scale_divisor;
 
-        scale = scale.clamp(0.075, 6.5);
-        let normalized_displacement = displacement.normalize();
-        let power = normalized_displacement * scale;
-        power
+        scale = scale.clamp(config.min_scale, config.max_scale);
+        //No direction to shoot in when the mouse is on the origin
+        match displacement.try_normalize() {
+            Some(direction) => direction * scale,
+            None => Vector2D::zero(),
+        }
     }
 
-    pub fn calculate_speed(
+    fn stroke_speed(
         origin: Vector2D<f32>,
         mouse_coords: Vector2D<f32>,
         mode: ShootingMode,
+        config: &StrokeConfig,
     ) -> Vector2D<f32> {
-        let stroke_power = Self::calculate_stroke_power(origin, mouse_coords);
+        let stroke_power = Self::stroke_power(origin, mouse_coords, config);
 
-        let mut speed = match mode {
-            ShootingMode::Normal => stroke_power,
-            ShootingMode::Reverse => -stroke_power,
-            ShootingMode::Right => Vector2D::new(stroke_power.y, -stroke_power.x),
-            ShootingMode::Left => Vector2D::new(-stroke_power.y, stroke_power.x),
-        };
+        let mut speed = mode.transform(stroke_power);
 
         let speed_length = speed.length();
-        let mut speed_length_divided = speed_length / 6.5;
+        let mut speed_length_divided = speed_length / config.max_scale;
         speed_length_divided *= speed_length_divided;
 
         // TODO: Add randomization logic
@@ -62,10 +211,53 @@ impl Stroke {
 
 #[cfg(test)]
 mod tests {
+    use crate::stroke::PhysicsConfig;
     use crate::stroke::ShootingMode;
     use crate::stroke::Stroke;
+    use crate::stroke::StrokeConfig;
     use crate::vector2d::Vector2D;
 
+    #[test]
+    fn power_indicator_test() {
+        let config = StrokeConfig::default();
+        let origin = Vector2D::new(52.5, 187.5);
+        let full = config.dead_zone + config.max_scale * config.scale_divisor;
+
+        let indicator = Stroke::power_indicator(origin, origin + Vector2D::new(0.0, full));
+        assert!(approx_eq(indicator.strength01, 1.0));
+        assert!(indicator
+            .direction
+            .approx_eq(&Vector2D::new(0.0, 1.0), EPSILON));
+        let indicator = Stroke::power_indicator(origin, origin + Vector2D::new(full * 2.0, 0.0));
+        assert_eq!(indicator.strength01, 1.0);
+
+        let indicator =
+            Stroke::power_indicator(origin, origin + Vector2D::new(-config.dead_zone, 0.0));
+        assert!(indicator.strength01 < 0.02);
+        assert!(indicator
+            .direction
+            .approx_eq(&Vector2D::new(-1.0, 0.0), EPSILON));
+
+        let indicator = Stroke::power_indicator(origin, origin);
+        assert_eq!(indicator.strength01, 0.0);
+        assert_eq!((indicator.direction.x, indicator.direction.y), (0.0, 0.0));
+    }
+
+    #[test]
+    fn shooting_mode_transform_test() {
+        let power = Vector2D::new(3.5f32, -1.25);
+        let expected = [
+            (ShootingMode::Normal, power),
+            (ShootingMode::Reverse, -power),
+            (ShootingMode::Right, power.perpendicular_cw()),
+            (ShootingMode::Left, power.perpendicular_ccw()),
+        ];
+        for (mode, expected) in expected {
+            let transformed = mode.transform(power);
+            assert_eq!((transformed.x, transformed.y), (expected.x, expected.y));
+        }
+    }
+
     #[test]
     fn shooting_mode_next_test() {
         assert_eq!(ShootingMode::Normal.next(), ShootingMode::Reverse);
@@ -74,6 +266,22 @@ mod tests {
         assert_eq!(ShootingMode::Left.next(), ShootingMode::Normal);
     }
 
+    #[test]
+    fn shooting_mode_from_str_test() {
+        let modes = [
+            ShootingMode::Normal,
+            ShootingMode::Reverse,
+            ShootingMode::Right,
+            ShootingMode::Left,
+        ];
+        for mode in modes {
+            assert_eq!(mode.to_string().parse::<ShootingMode>(), Ok(mode));
+        }
+        assert_eq!("Reverse".parse::<ShootingMode>(), Ok(ShootingMode::Reverse));
+        assert_eq!("LEFT".parse::<ShootingMode>(), Ok(ShootingMode::Left));
+        assert!("sideways".parse::<ShootingMode>().is_err());
+    }
+
     #[test]
     fn stroke_power_test() {
         let point = Vector2D::new(52.5, 187.5);
@@ -92,8 +300,7 @@ mod tests {
 
         for (&coords, &result) in mouse_coords.iter().zip(results.iter()) {
             let power = Stroke::calculate_stroke_power(point, coords);
-            assert!(approx_eq(power.x, result.x));
-            assert!(approx_eq(power.y, result.y));
+            assert!(power.approx_eq(&result, EPSILON));
         }
     }
     #[test]
@@ -129,12 +336,97 @@ mod tests {
             .zip(shooting_modes.iter().zip(results.iter()))
         {
             let speed = Stroke::calculate_speed(location, mouse_coord, shooting_mode);
-            assert!(approx_eq(speed.x, expected_result.x));
-            assert!(approx_eq(speed.y, expected_result.y));
+            assert!(speed.approx_eq(&expected_result, EPSILON));
         }
     }
 
+    #[test]
+    fn perpendicular_modes_test() {
+        let origin = Vector2D::new(37.5, 52.5);
+        let mouse = Vector2D::new(285.0, 205.0);
+        let normal = Stroke::calculate_speed(origin, mouse, ShootingMode::Normal);
+        let right = Stroke::calculate_speed(origin, mouse, ShootingMode::Right);
+        let left = Stroke::calculate_speed(origin, mouse, ShootingMode::Left);
+
+        //All modes add the same offset, remove it before comparing
+        let power = Stroke::calculate_stroke_power(origin, mouse);
+        let scale = power.length() / 6.5;
+        let offset = Vector2D::new(1.0, 1.0) * (scale * scale / 100000.0 - 0.25);
+        let expected_right = power.perpendicular_cw();
+        let expected_left = power.perpendicular_ccw();
+        assert!((right - offset).approx_eq(&expected_right, EPSILON));
+        assert!((left - offset).approx_eq(&expected_left, EPSILON));
+        assert!((normal - offset).approx_eq(&power, EPSILON));
+    }
+
+    #[test]
+    fn stroke_instance_test() {
+        let origin = Vector2D::new(37.5, 52.5);
+        let mouse = Vector2D::new(285.0, 205.0);
+        let stroke = Stroke::new(origin, PhysicsConfig::default());
+
+        let power = stroke.power(mouse);
+        let expected = Stroke::calculate_stroke_power(origin, mouse);
+        assert!(power.approx_eq(&expected, EPSILON));
+
+        let speed = stroke.speed(mouse, ShootingMode::Left);
+        let expected = Stroke::calculate_speed(origin, mouse, ShootingMode::Left);
+        assert!(speed.approx_eq(&expected, EPSILON));
+    }
+
+    #[test]
+    fn stroke_config_test() {
+        let origin = Vector2D::new(37.5, 52.5);
+        let mouse = Vector2D::new(137.5, 52.5);
+        let default = Stroke::calculate_stroke_power_with(origin, mouse, &StrokeConfig::default());
+        assert!(default.approx_eq(&Stroke::calculate_stroke_power(origin, mouse), EPSILON));
+
+        let config = StrokeConfig {
+            dead_zone: 50.0,
+            ..StrokeConfig::default()
+        };
+        let reduced = Stroke::calculate_stroke_power_with(origin, mouse, &config);
+        assert!(reduced.length() < default.length());
+        assert!(approx_eq(reduced.x, 50.0 / 30.0));
+
+        let stroke = Stroke::new(
+            origin,
+            PhysicsConfig {
+                stroke: config,
+                ..PhysicsConfig::default()
+            },
+        );
+        assert!(stroke.power(mouse).approx_eq(&reduced, EPSILON));
+    }
+
+    #[test]
+    fn snap_direction_test() {
+        let snapped = Stroke::snap_direction(Vector2D::new(3.0, 0.2), 4);
+        assert!(approx_eq(snapped.x, Vector2D::new(3.0f32, 0.2).length()));
+        assert!(approx_eq(snapped.y, 0.0));
+
+        let snapped = Stroke::snap_direction(Vector2D::new(-0.1, -2.0), 4);
+        assert!(approx_eq(snapped.x, 0.0));
+        assert!(snapped.y < 0.0);
+
+        let snapped = Stroke::snap_direction(Vector2D::new(1.0, 1.1), 8);
+        assert!(approx_eq(snapped.x, snapped.y));
+    }
+
+    #[test]
+    fn zero_displacement_test() {
+        let origin = Vector2D::new(52.5, 187.5);
+
+        let power = Stroke::calculate_stroke_power(origin, origin);
+        assert_eq!((power.x, power.y), (0.0, 0.0));
+
+        let speed = Stroke::calculate_speed(origin, origin, ShootingMode::Normal);
+        assert!(speed.x.is_finite() && speed.y.is_finite());
+    }
+
+    const EPSILON: f32 = 0.00001;
+
     fn approx_eq(a: f32, b: f32) -> bool {
-        (a - b).abs() < 0.00001
+        (a - b).abs() < EPSILON
     }
 }
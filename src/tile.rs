@@ -2,12 +2,16 @@ use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 use thiserror::Error;
 
+use crate::direction::{Direction4, Direction8};
+use crate::gamemap::{Assets, GameMap, GameMapTile};
 use crate::vector2d::Vector2D;
 const MAGIC: f32 = std::f32::consts::FRAC_1_SQRT_2;
 const DOWNHILLSPEED: f32 = 0.025;
 
 #[derive(Debug, Error)]
 pub enum TileCreationError {
+    #[error("Invalid special parse value: {0}")]
+    InvalidSpecialParse(i32),
     #[error("Invalid special value: {0}")]
     InvalidSpecial(i32),
     #[error("Invalid shape value: {0}")]
@@ -16,6 +20,8 @@ pub enum TileCreationError {
     InvalidBackground(i32),
     #[error("Invalid foreground value: {0}")]
     InvalidForeground(i32),
+    #[error("Invalid element value: {0}")]
+    InvalidElement(i32),
 }
 
 #[derive(PartialEq, Eq, Debug, Copy, Clone, FromPrimitive)]
@@ -56,6 +62,26 @@ pub enum Special {
     StartPositionGreen,   //51 27
 }
 
+#[derive(PartialEq, Eq, Debug, Copy, Clone, Hash)]
+pub enum TeamColor {
+    Blue,
+    Red,
+    Yellow,
+    Green,
+}
+
+impl TeamColor {
+    pub fn all() -> &'static [TeamColor] {
+        const ALL: [TeamColor; 4] = [
+            TeamColor::Blue,
+            TeamColor::Red,
+            TeamColor::Yellow,
+            TeamColor::Green,
+        ];
+        &ALL
+    }
+}
+
 #[derive(PartialEq, Eq, Debug, Copy, Clone, FromPrimitive, Hash)]
 pub enum Element {
     Grass,       //0
@@ -119,7 +145,7 @@ pub enum Shape {
     QuaterNW,
 }
 
-#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+#[derive(PartialEq, Eq, Debug, Copy, Clone, Hash)]
 pub struct Tile {
     pub special: Option<Special>,
     pub shape: Option<Shape>,
@@ -127,7 +153,67 @@ pub struct Tile {
     pub foreground: Element,
 }
 
+//A tile stored as its tile code
+#[derive(PartialEq, Eq, Debug, Copy, Clone, Hash)]
+#[repr(transparent)]
+pub struct PackedTile(pub u32);
+
+impl TryFrom<i32> for Special {
+    type Error = TileCreationError;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        FromPrimitive::from_i32(value).ok_or(TileCreationError::InvalidSpecial(value))
+    }
+}
+
+impl TryFrom<i32> for Element {
+    type Error = TileCreationError;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        FromPrimitive::from_i32(value).ok_or(TileCreationError::InvalidElement(value))
+    }
+}
+
 impl Special {
+    pub const BIG_MINE_IMPULSE: f32 = 6.5;
+    pub const MINE_IMPULSE: f32 = 4.0;
+
+    pub fn all() -> &'static [Special] {
+        const ALL: [Special; 28] = [
+            Special::StartPosition,
+            Special::Hole,
+            Special::FakeHole,
+            Special::MoveableBlock,
+            Special::Mine,
+            Special::BlownMine,
+            Special::BigMine,
+            Special::BlownBigMine,
+            Special::BlueTeleportStart,
+            Special::BlueTeleportExit,
+            Special::RedTeleportStart,
+            Special::RedTeleportExit,
+            Special::YellowTeleportStart,
+            Special::YellowTeleportExit,
+            Special::GreenTeleportStart,
+            Special::GreenTeleportExit,
+            Special::FullBreakable,
+            Special::ThreeQuaterBreakable,
+            Special::HalfBreakable,
+            Special::QuaterBreakable,
+            Special::MagnetAttract,
+            Special::MagnetRepel,
+            Special::MoveableBlock2,
+            Special::SunkMoveableBlock,
+            Special::StartPositionBlue,
+            Special::StartPositionRed,
+            Special::StartPositionYellow,
+            Special::StartPositionGreen,
+        ];
+        &ALL
+    }
+
+    //Whether the special is a block, for drawing and map statistics. Collision
+    //goes through blocks_ball.
     pub fn is_solid(&self) -> bool {
         matches!(
             self,
@@ -140,16 +226,82 @@ impl Special {
         )
     }
 
-    pub fn get_matching_teleport(&self) -> Option<Special> {
+    //Whether the ball bounces off the special, every solid special does. A
+    //SunkMoveableBlock has been pushed into water and is part of the floor now,
+    //so the ball rolls over it.
+    pub fn blocks_ball(&self) -> bool {
+        self.is_solid()
+    }
+
+    //Only a real hole ends the hole, FakeHole just looks like one
+    pub fn is_capturing_hole(&self) -> bool {
+        *self == Special::Hole
+    }
+
+    //What the special is drawn as, if it is disguised as something else
+    pub fn looks_like(&self) -> Option<Special> {
         match self {
-            Special::RedTeleportStart => Some(Special::RedTeleportExit),
-            Special::BlueTeleportStart => Some(Special::BlueTeleportExit),
-            Special::GreenTeleportStart => Some(Special::GreenTeleportExit),
-            Special::YellowTeleportStart => Some(Special::YellowTeleportExit),
+            Special::FakeHole => Some(Special::Hole),
             _ => None,
         }
     }
 
+    pub fn get_matching_teleport(&self) -> Option<Special> {
+        if self.is_teleport_start() {
+            self.team_color().map(Special::teleport_exit)
+        } else {
+            None
+        }
+    }
+
+    pub fn teleport_start(color: TeamColor) -> Special {
+        match color {
+            TeamColor::Blue => Special::BlueTeleportStart,
+            TeamColor::Red => Special::RedTeleportStart,
+            TeamColor::Yellow => Special::YellowTeleportStart,
+            TeamColor::Green => Special::GreenTeleportStart,
+        }
+    }
+
+    pub fn teleport_exit(color: TeamColor) -> Special {
+        match color {
+            TeamColor::Blue => Special::BlueTeleportExit,
+            TeamColor::Red => Special::RedTeleportExit,
+            TeamColor::Yellow => Special::YellowTeleportExit,
+            TeamColor::Green => Special::GreenTeleportExit,
+        }
+    }
+
+    pub fn start_position(color: TeamColor) -> Special {
+        match color {
+            TeamColor::Blue => Special::StartPositionBlue,
+            TeamColor::Red => Special::StartPositionRed,
+            TeamColor::Yellow => Special::StartPositionYellow,
+            TeamColor::Green => Special::StartPositionGreen,
+        }
+    }
+
+    pub fn is_teleport_exit(&self) -> bool {
+        matches!(
+            self,
+            Special::YellowTeleportExit
+                | Special::RedTeleportExit
+                | Special::GreenTeleportExit
+                | Special::BlueTeleportExit
+        )
+    }
+
+    pub fn is_start_position(&self) -> bool {
+        matches!(
+            self,
+            Special::StartPosition
+                | Special::StartPositionBlue
+                | Special::StartPositionRed
+                | Special::StartPositionYellow
+                | Special::StartPositionGreen
+        )
+    }
+
     pub fn is_teleport_start(&self) -> bool {
         matches!(
             self,
@@ -160,6 +312,39 @@ impl Special {
         )
     }
 
+    pub fn team_color(&self) -> Option<TeamColor> {
+        match self {
+            Special::BlueTeleportStart | Special::BlueTeleportExit | Special::StartPositionBlue => {
+                Some(TeamColor::Blue)
+            }
+            Special::RedTeleportStart | Special::RedTeleportExit | Special::StartPositionRed => {
+                Some(TeamColor::Red)
+            }
+            Special::YellowTeleportStart
+            | Special::YellowTeleportExit
+            | Special::StartPositionYellow => Some(TeamColor::Yellow),
+            Special::GreenTeleportStart
+            | Special::GreenTeleportExit
+            | Special::StartPositionGreen => Some(TeamColor::Green),
+            _ => None,
+        }
+    }
+
+    //approach is the ball's offset from the center of the special. Mines push
+    //the ball straight away from their center, or up when hit dead center.
+    pub fn impulse_on_hit(&self, approach: Vector2D<f32>) -> Option<Vector2D<f32>> {
+        let strength = match self {
+            Special::Mine => Special::MINE_IMPULSE,
+            Special::BigMine => Special::BIG_MINE_IMPULSE,
+            _ => return None,
+        };
+        if approach.length() == 0.0 {
+            Some(Vector2D::new(0.0, -strength))
+        } else {
+            Some(approach.normalize() * strength)
+        }
+    }
+
     pub fn get_friction(&self) -> f32 {
         match self {
             Special::Hole => 0.96,
@@ -174,9 +359,152 @@ impl Special {
             _ => 1.0,
         }
     }
+
+    //Position of the sprite in specials.png, which follows the enum order
+    pub fn sprite_index(&self) -> usize {
+        *self as usize
+    }
+
+    pub fn to_ascii_char(&self) -> char {
+        match self {
+            Special::StartPosition => 'S',
+            Special::Hole => 'O',
+            Special::FakeHole => 'o',
+            Special::MoveableBlock => 'M',
+            Special::Mine => 'X',
+            Special::BlownMine => 'x',
+            Special::BigMine => 'Z',
+            Special::BlownBigMine => 'z',
+            Special::BlueTeleportStart => 'B',
+            Special::BlueTeleportExit => 'b',
+            Special::RedTeleportStart => 'R',
+            Special::RedTeleportExit => 'r',
+            Special::YellowTeleportStart => 'Y',
+            Special::YellowTeleportExit => 'y',
+            Special::GreenTeleportStart => 'G',
+            Special::GreenTeleportExit => 'g',
+            Special::FullBreakable => 'K',
+            Special::ThreeQuaterBreakable => 'T',
+            Special::HalfBreakable => 'H',
+            Special::QuaterBreakable => 'Q',
+            Special::MagnetAttract => 'A',
+            Special::MagnetRepel => 'P',
+            Special::MoveableBlock2 => 'm',
+            Special::SunkMoveableBlock => 'U',
+            Special::StartPositionBlue => 'C',
+            Special::StartPositionRed => 'D',
+            Special::StartPositionYellow => 'E',
+            Special::StartPositionGreen => 'F',
+        }
+    }
+
+    //The variant name, e.g. for palettes and serializing by name
+    pub fn name(&self) -> &'static str {
+        match self {
+            Special::StartPosition => "StartPosition",
+            Special::Hole => "Hole",
+            Special::FakeHole => "FakeHole",
+            Special::MoveableBlock => "MoveableBlock",
+            Special::Mine => "Mine",
+            Special::BlownMine => "BlownMine",
+            Special::BigMine => "BigMine",
+            Special::BlownBigMine => "BlownBigMine",
+            Special::BlueTeleportStart => "BlueTeleportStart",
+            Special::BlueTeleportExit => "BlueTeleportExit",
+            Special::RedTeleportStart => "RedTeleportStart",
+            Special::RedTeleportExit => "RedTeleportExit",
+            Special::YellowTeleportStart => "YellowTeleportStart",
+            Special::YellowTeleportExit => "YellowTeleportExit",
+            Special::GreenTeleportStart => "GreenTeleportStart",
+            Special::GreenTeleportExit => "GreenTeleportExit",
+            Special::FullBreakable => "FullBreakable",
+            Special::ThreeQuaterBreakable => "ThreeQuaterBreakable",
+            Special::HalfBreakable => "HalfBreakable",
+            Special::QuaterBreakable => "QuaterBreakable",
+            Special::MagnetAttract => "MagnetAttract",
+            Special::MagnetRepel => "MagnetRepel",
+            Special::MoveableBlock2 => "MoveableBlock2",
+            Special::SunkMoveableBlock => "SunkMoveableBlock",
+            Special::StartPositionBlue => "StartPositionBlue",
+            Special::StartPositionRed => "StartPositionRed",
+            Special::StartPositionYellow => "StartPositionYellow",
+            Special::StartPositionGreen => "StartPositionGreen",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Special> {
+        Special::all()
+            .iter()
+            .copied()
+            .find(|value| value.name() == name)
+    }
+
+    pub fn from_char(c: char) -> Option<Special> {
+        match c {
+            'S' => Some(Special::StartPosition),
+            'O' => Some(Special::Hole),
+            'o' => Some(Special::FakeHole),
+            'M' => Some(Special::MoveableBlock),
+            'X' => Some(Special::Mine),
+            'x' => Some(Special::BlownMine),
+            'Z' => Some(Special::BigMine),
+            'z' => Some(Special::BlownBigMine),
+            'B' => Some(Special::BlueTeleportStart),
+            'b' => Some(Special::BlueTeleportExit),
+            'R' => Some(Special::RedTeleportStart),
+            'r' => Some(Special::RedTeleportExit),
+            'Y' => Some(Special::YellowTeleportStart),
+            'y' => Some(Special::YellowTeleportExit),
+            'G' => Some(Special::GreenTeleportStart),
+            'g' => Some(Special::GreenTeleportExit),
+            'K' => Some(Special::FullBreakable),
+            'T' => Some(Special::ThreeQuaterBreakable),
+            'H' => Some(Special::HalfBreakable),
+            'Q' => Some(Special::QuaterBreakable),
+            'A' => Some(Special::MagnetAttract),
+            'P' => Some(Special::MagnetRepel),
+            'm' => Some(Special::MoveableBlock2),
+            'U' => Some(Special::SunkMoveableBlock),
+            'C' => Some(Special::StartPositionBlue),
+            'D' => Some(Special::StartPositionRed),
+            'E' => Some(Special::StartPositionYellow),
+            'F' => Some(Special::StartPositionGreen),
+            _ => None,
+        }
+    }
 }
 
 impl Element {
+    pub fn all() -> &'static [Element] {
+        const ALL: [Element; 24] = [
+            Element::Grass,
+            Element::Dirt,
+            Element::Mud,
+            Element::Ice,
+            Element::SpeedN,
+            Element::SpeedNE,
+            Element::SpeedE,
+            Element::SpeedSE,
+            Element::SpeedS,
+            Element::SpeedSW,
+            Element::SpeedW,
+            Element::SpeedNW,
+            Element::Water,
+            Element::Acid,
+            Element::WaterSwamp,
+            Element::AcidSwamp,
+            Element::Block,
+            Element::StickyBlock,
+            Element::BouncyBlock,
+            Element::FakeBlock,
+            Element::OnewayN,
+            Element::OnewayE,
+            Element::OnewayS,
+            Element::OnewayW,
+        ];
+        &ALL
+    }
+
     pub fn is_solid(&self) -> bool {
         matches!(
             self,
@@ -190,6 +518,11 @@ impl Element {
         )
     }
 
+    //Passable but drawn like Block when Settings::illusion_wall_shadows is set
+    pub fn is_illusion(&self) -> bool {
+        *self == Element::FakeBlock
+    }
+
     pub fn is_oneway(&self) -> bool {
         matches!(
             self,
@@ -211,19 +544,44 @@ impl Element {
         )
     }
 
+    pub fn downhill_direction(&self) -> Option<Direction8> {
+        match self {
+            Element::SpeedN => Some(Direction8::N),
+            Element::SpeedNE => Some(Direction8::NE),
+            Element::SpeedE => Some(Direction8::E),
+            Element::SpeedSE => Some(Direction8::SE),
+            Element::SpeedS => Some(Direction8::S),
+            Element::SpeedSW => Some(Direction8::SW),
+            Element::SpeedW => Some(Direction8::W),
+            Element::SpeedNW => Some(Direction8::NW),
+            _ => None,
+        }
+    }
+
+    //Direction the ball is allowed to pass through
+    pub fn oneway_direction(&self) -> Option<Direction4> {
+        match self {
+            Element::OnewayN => Some(Direction4::N),
+            Element::OnewayE => Some(Direction4::E),
+            Element::OnewayS => Some(Direction4::S),
+            Element::OnewayW => Some(Direction4::W),
+            _ => None,
+        }
+    }
+
     pub fn get_downhill_speed(&self) -> Vector2D<f32> {
-        let (y, x) = match self {
-            Element::SpeedN => (-DOWNHILLSPEED, 0.0),
-            Element::SpeedNE => (-DOWNHILLSPEED * MAGIC, DOWNHILLSPEED * MAGIC),
-            Element::SpeedE => (0.0, DOWNHILLSPEED),
-            Element::SpeedSE => (DOWNHILLSPEED * MAGIC, DOWNHILLSPEED * MAGIC),
-            Element::SpeedS => (DOWNHILLSPEED, 0.0),
-            Element::SpeedSW => (DOWNHILLSPEED * MAGIC, -DOWNHILLSPEED * MAGIC),
-            Element::SpeedW => (0.0, -DOWNHILLSPEED),
-            Element::SpeedNW => (-DOWNHILLSPEED * MAGIC, -DOWNHILLSPEED * MAGIC),
-            _ => (0.0, 0.0),
-        };
-        Vector2D::new(x, y)
+        match self.downhill_direction() {
+            Some(direction) => {
+                let vector = direction.to_vector();
+                let speed = if direction.is_diagonal() {
+                    DOWNHILLSPEED * MAGIC
+                } else {
+                    DOWNHILLSPEED
+                };
+                Vector2D::new(vector.x as f32 * speed, vector.y as f32 * speed)
+            }
+            None => Vector2D::new(0.0, 0.0),
+        }
     }
 
     pub fn get_friction(&self) -> f32 {
@@ -247,6 +605,259 @@ impl Element {
             Element::OnewayN | Element::OnewayE | Element::OnewayS | Element::OnewayW => 0.995,
         }
     }
+
+    pub fn flip_horizontal(&self) -> Element {
+        match self {
+            Element::SpeedNE => Element::SpeedNW,
+            Element::SpeedE => Element::SpeedW,
+            Element::SpeedSE => Element::SpeedSW,
+            Element::SpeedSW => Element::SpeedSE,
+            Element::SpeedW => Element::SpeedE,
+            Element::SpeedNW => Element::SpeedNE,
+            Element::OnewayE => Element::OnewayW,
+            Element::OnewayW => Element::OnewayE,
+            element => *element,
+        }
+    }
+
+    pub fn flip_vertical(&self) -> Element {
+        match self {
+            Element::SpeedN => Element::SpeedS,
+            Element::SpeedNE => Element::SpeedSE,
+            Element::SpeedSE => Element::SpeedNE,
+            Element::SpeedS => Element::SpeedN,
+            Element::SpeedSW => Element::SpeedNW,
+            Element::SpeedNW => Element::SpeedSW,
+            Element::OnewayN => Element::OnewayS,
+            Element::OnewayS => Element::OnewayN,
+            element => *element,
+        }
+    }
+
+    //Speed tiles follow the numpad directions
+    pub fn to_ascii_char(&self) -> char {
+        match self {
+            Element::Grass => '.',
+            Element::Dirt => ',',
+            Element::Mud => ':',
+            Element::Ice => '_',
+            Element::SpeedN => '8',
+            Element::SpeedNE => '9',
+            Element::SpeedE => '6',
+            Element::SpeedSE => '3',
+            Element::SpeedS => '2',
+            Element::SpeedSW => '1',
+            Element::SpeedW => '4',
+            Element::SpeedNW => '7',
+            Element::Water => '~',
+            Element::Acid => '%',
+            Element::WaterSwamp => '-',
+            Element::AcidSwamp => '=',
+            Element::Block => '#',
+            Element::StickyBlock => '&',
+            Element::BouncyBlock => '@',
+            Element::FakeBlock => '+',
+            Element::OnewayN => '^',
+            Element::OnewayE => '>',
+            Element::OnewayS => 'v',
+            Element::OnewayW => '<',
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Element::Grass => "Grass",
+            Element::Dirt => "Dirt",
+            Element::Mud => "Mud",
+            Element::Ice => "Ice",
+            Element::SpeedN => "SpeedN",
+            Element::SpeedNE => "SpeedNE",
+            Element::SpeedE => "SpeedE",
+            Element::SpeedSE => "SpeedSE",
+            Element::SpeedS => "SpeedS",
+            Element::SpeedSW => "SpeedSW",
+            Element::SpeedW => "SpeedW",
+            Element::SpeedNW => "SpeedNW",
+            Element::Water => "Water",
+            Element::Acid => "Acid",
+            Element::WaterSwamp => "WaterSwamp",
+            Element::AcidSwamp => "AcidSwamp",
+            Element::Block => "Block",
+            Element::StickyBlock => "StickyBlock",
+            Element::BouncyBlock => "BouncyBlock",
+            Element::FakeBlock => "FakeBlock",
+            Element::OnewayN => "OnewayN",
+            Element::OnewayE => "OnewayE",
+            Element::OnewayS => "OnewayS",
+            Element::OnewayW => "OnewayW",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Element> {
+        Element::all()
+            .iter()
+            .copied()
+            .find(|value| value.name() == name)
+    }
+
+    pub fn from_char(c: char) -> Option<Element> {
+        match c {
+            '.' => Some(Element::Grass),
+            ',' => Some(Element::Dirt),
+            ':' => Some(Element::Mud),
+            '_' => Some(Element::Ice),
+            '8' => Some(Element::SpeedN),
+            '9' => Some(Element::SpeedNE),
+            '6' => Some(Element::SpeedE),
+            '3' => Some(Element::SpeedSE),
+            '2' => Some(Element::SpeedS),
+            '1' => Some(Element::SpeedSW),
+            '4' => Some(Element::SpeedW),
+            '7' => Some(Element::SpeedNW),
+            '~' => Some(Element::Water),
+            '%' => Some(Element::Acid),
+            '-' => Some(Element::WaterSwamp),
+            '=' => Some(Element::AcidSwamp),
+            '#' => Some(Element::Block),
+            '&' => Some(Element::StickyBlock),
+            '@' => Some(Element::BouncyBlock),
+            '+' => Some(Element::FakeBlock),
+            '^' => Some(Element::OnewayN),
+            '>' => Some(Element::OnewayE),
+            'v' => Some(Element::OnewayS),
+            '<' => Some(Element::OnewayW),
+            _ => None,
+        }
+    }
+}
+
+impl Shape {
+    pub fn all() -> &'static [Shape] {
+        const ALL: [Shape; 28] = [
+            Shape::Blank,
+            Shape::BigCircle,
+            Shape::SmallCircle,
+            Shape::Diamond,
+            Shape::TriangleSE,
+            Shape::TriangleSW,
+            Shape::TriangleNW,
+            Shape::TriangleNE,
+            Shape::RoundedSE,
+            Shape::RoundedSW,
+            Shape::RoundedNW,
+            Shape::RoundedNE,
+            Shape::RoundedS,
+            Shape::RoundedE,
+            Shape::RoundedN,
+            Shape::RoundedW,
+            Shape::TriangleN,
+            Shape::TriangleE,
+            Shape::TriangleS,
+            Shape::TriangleW,
+            Shape::TriangleNS,
+            Shape::TriangleWE,
+            Shape::HalfW,
+            Shape::HalfS,
+            Shape::QuaterNE,
+            Shape::QuaterSE,
+            Shape::QuaterSW,
+            Shape::QuaterNW,
+        ];
+        &ALL
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Shape::Blank => "Blank",
+            Shape::BigCircle => "BigCircle",
+            Shape::SmallCircle => "SmallCircle",
+            Shape::Diamond => "Diamond",
+            Shape::TriangleSE => "TriangleSE",
+            Shape::TriangleSW => "TriangleSW",
+            Shape::TriangleNW => "TriangleNW",
+            Shape::TriangleNE => "TriangleNE",
+            Shape::RoundedSE => "RoundedSE",
+            Shape::RoundedSW => "RoundedSW",
+            Shape::RoundedNW => "RoundedNW",
+            Shape::RoundedNE => "RoundedNE",
+            Shape::RoundedS => "RoundedS",
+            Shape::RoundedE => "RoundedE",
+            Shape::RoundedN => "RoundedN",
+            Shape::RoundedW => "RoundedW",
+            Shape::TriangleN => "TriangleN",
+            Shape::TriangleE => "TriangleE",
+            Shape::TriangleS => "TriangleS",
+            Shape::TriangleW => "TriangleW",
+            Shape::TriangleNS => "TriangleNS",
+            Shape::TriangleWE => "TriangleWE",
+            Shape::HalfW => "HalfW",
+            Shape::HalfS => "HalfS",
+            Shape::QuaterNE => "QuaterNE",
+            Shape::QuaterSE => "QuaterSE",
+            Shape::QuaterSW => "QuaterSW",
+            Shape::QuaterNW => "QuaterNW",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Shape> {
+        Shape::all()
+            .iter()
+            .copied()
+            .find(|value| value.name() == name)
+    }
+
+    //Position of the sprite in shapes.png, which follows the enum order
+    pub fn sprite_index(&self) -> usize {
+        *self as usize
+    }
+
+    //HalfW has no east counterpart and is left as is, Tile::flip_horizontal
+    //mirrors it by swapping the elements
+    pub fn flip_horizontal(&self) -> Shape {
+        match self {
+            Shape::TriangleSE => Shape::TriangleSW,
+            Shape::TriangleSW => Shape::TriangleSE,
+            Shape::TriangleNW => Shape::TriangleNE,
+            Shape::TriangleNE => Shape::TriangleNW,
+            Shape::RoundedSE => Shape::RoundedSW,
+            Shape::RoundedSW => Shape::RoundedSE,
+            Shape::RoundedNW => Shape::RoundedNE,
+            Shape::RoundedNE => Shape::RoundedNW,
+            Shape::RoundedE => Shape::RoundedW,
+            Shape::RoundedW => Shape::RoundedE,
+            Shape::TriangleE => Shape::TriangleW,
+            Shape::TriangleW => Shape::TriangleE,
+            Shape::QuaterNE => Shape::QuaterNW,
+            Shape::QuaterSE => Shape::QuaterSW,
+            Shape::QuaterSW => Shape::QuaterSE,
+            Shape::QuaterNW => Shape::QuaterNE,
+            shape => *shape,
+        }
+    }
+
+    //HalfS has no north counterpart and is left as is, Tile::flip_vertical
+    //mirrors it by swapping the elements
+    pub fn flip_vertical(&self) -> Shape {
+        match self {
+            Shape::TriangleSE => Shape::TriangleNE,
+            Shape::TriangleSW => Shape::TriangleNW,
+            Shape::TriangleNW => Shape::TriangleSW,
+            Shape::TriangleNE => Shape::TriangleSE,
+            Shape::RoundedSE => Shape::RoundedNE,
+            Shape::RoundedSW => Shape::RoundedNW,
+            Shape::RoundedNW => Shape::RoundedSW,
+            Shape::RoundedNE => Shape::RoundedSE,
+            Shape::RoundedS => Shape::RoundedN,
+            Shape::RoundedN => Shape::RoundedS,
+            Shape::TriangleN => Shape::TriangleS,
+            Shape::TriangleS => Shape::TriangleN,
+            Shape::QuaterNE => Shape::QuaterSE,
+            Shape::QuaterSE => Shape::QuaterNE,
+            Shape::QuaterSW => Shape::QuaterNW,
+            Shape::QuaterNW => Shape::QuaterSW,
+            shape => *shape,
+        }
+    }
 }
 
 impl Tile {
@@ -264,6 +875,37 @@ impl Tile {
         }
     }
 
+    //What is shown at fx and fy, both 0.0..1.0 across the tile, independent of
+    //Map::TILESIZE. Sprites are sampled at the nearest pixel.
+    pub fn resolve_at(&self, assets: &Assets, fx: f32, fy: f32) -> GameMapTile {
+        GameMap::resolve_tile(self, assets, |asset, i| asset.sample_at(i, fx, fy))
+    }
+
+    //A tile has either a special or a shape, so setting one clears the other
+    pub fn with_special(self, special: Special) -> Self {
+        Self {
+            special: Some(special),
+            shape: None,
+            ..self
+        }
+    }
+
+    pub fn with_shape(self, shape: Shape) -> Self {
+        Self {
+            special: None,
+            shape: Some(shape),
+            ..self
+        }
+    }
+
+    pub fn with_background(self, background: Element) -> Self {
+        Self { background, ..self }
+    }
+
+    pub fn with_foreground(self, foreground: Element) -> Self {
+        Self { foreground, ..self }
+    }
+
     pub fn from_i32s(
         special_value: i32,
         shape_value: i32,
@@ -271,27 +913,22 @@ impl Tile {
         foreground_value: i32,
     ) -> Result<Self, TileCreationError> {
         let special_parse: SpecialParse = FromPrimitive::from_i32(special_value)
-            .ok_or_else(|| TileCreationError::InvalidSpecial(special_value))?;
+            .ok_or(TileCreationError::InvalidSpecialParse(special_value))?;
         let background = FromPrimitive::from_i32(background_value)
-            .ok_or_else(|| TileCreationError::InvalidBackground(background_value))?;
+            .ok_or(TileCreationError::InvalidBackground(background_value))?;
         let foreground = FromPrimitive::from_i32(foreground_value)
-            .ok_or_else(|| TileCreationError::InvalidForeground(foreground_value))?;
+            .ok_or(TileCreationError::InvalidForeground(foreground_value))?;
 
+        //shape_value is a Shape for normal tiles and a Special for special tiles
         let (special, shape) = match special_parse {
             SpecialParse::Normal => (
                 None,
                 Some(
                     FromPrimitive::from_i32(shape_value)
-                        .ok_or_else(|| TileCreationError::InvalidShape(shape_value))?,
+                        .ok_or(TileCreationError::InvalidShape(shape_value))?,
                 ),
             ),
-            _ => (
-                Some(
-                    FromPrimitive::from_i32(shape_value)
-                        .ok_or_else(|| TileCreationError::InvalidShape(shape_value))?,
-                ),
-                None,
-            ),
+            SpecialParse::Special => (Some(Special::try_from(shape_value)?), None),
         };
 
         Ok(Tile {
@@ -308,36 +945,12 @@ impl Tile {
         let background_value = (tile_code >> 8) % 256;
         let foreground_value = tile_code % 256;
 
-        let special_parse: SpecialParse = FromPrimitive::from_i32(special_value)
-            .ok_or_else(|| TileCreationError::InvalidSpecial(special_value))?;
-        let background = FromPrimitive::from_i32(background_value)
-            .ok_or_else(|| TileCreationError::InvalidBackground(background_value))?;
-        let foreground = FromPrimitive::from_i32(foreground_value)
-            .ok_or_else(|| TileCreationError::InvalidForeground(foreground_value))?;
-
-        let (special, shape) = match special_parse {
-            SpecialParse::Normal => (
-                None,
-                Some(
-                    FromPrimitive::from_i32(shape_value)
-                        .ok_or_else(|| TileCreationError::InvalidShape(shape_value))?,
-                ),
-            ),
-            _ => (
-                Some(
-                    FromPrimitive::from_i32(shape_value)
-                        .ok_or_else(|| TileCreationError::InvalidShape(shape_value))?,
-                ),
-                None,
-            ),
-        };
-
-        Ok(Tile {
-            special,
-            shape,
-            background,
-            foreground,
-        })
+        Tile::from_i32s(
+            special_value,
+            shape_value,
+            background_value,
+            foreground_value,
+        )
     }
 
     pub fn to_tile_code(&self) -> i32 {
@@ -350,6 +963,83 @@ impl Tile {
 
         (special << 24) | (shape << 16) | (background << 8) | (foreground)
     }
+
+    pub fn flip_horizontal(&self) -> Tile {
+        let tile = Tile {
+            special: self.special,
+            shape: self.shape.map(|shape| shape.flip_horizontal()),
+            background: self.background.flip_horizontal(),
+            foreground: self.foreground.flip_horizontal(),
+        };
+        tile.swap_elements_if(Shape::HalfW)
+    }
+
+    pub fn flip_vertical(&self) -> Tile {
+        let tile = Tile {
+            special: self.special,
+            shape: self.shape.map(|shape| shape.flip_vertical()),
+            background: self.background.flip_vertical(),
+            foreground: self.foreground.flip_vertical(),
+        };
+        tile.swap_elements_if(Shape::HalfS)
+    }
+
+    //A half filled the other way round is the same half with the elements swapped
+    fn swap_elements_if(self, half: Shape) -> Tile {
+        if self.special.is_none() && self.shape == Some(half) {
+            Tile {
+                background: self.foreground,
+                foreground: self.background,
+                ..self
+            }
+        } else {
+            self
+        }
+    }
+
+    //Equal as drawn. The foreground only shows through a non-blank shape, and a
+    //special is drawn over the background with neither shape nor foreground.
+    pub fn visual_eq(&self, other: &Tile) -> bool {
+        self.visual_parts() == other.visual_parts()
+    }
+
+    fn visual_parts(&self) -> (Option<Special>, Option<(Shape, Element)>, Element) {
+        let shaped = match (self.special, self.shape) {
+            (None, Some(shape)) if shape != Shape::Blank => Some((shape, self.foreground)),
+            _ => None,
+        };
+        (self.special, shaped, self.background)
+    }
+
+    //Whether the ball can roll across the whole tile without hitting a wall or sinking
+    pub fn is_open(&self) -> bool {
+        let blocked = |element: Element| {
+            element.is_solid() || element == Element::Water || element == Element::Acid
+        };
+        match (self.special, self.shape) {
+            (Some(special), _) => !special.blocks_ball() && !blocked(self.background),
+            (None, Some(Shape::Blank) | None) => !blocked(self.background),
+            (None, Some(_)) => !blocked(self.background) && !blocked(self.foreground),
+        }
+    }
+
+    pub fn to_ascii_char(&self) -> char {
+        match (self.special, self.shape) {
+            (Some(special), _) => special.to_ascii_char(),
+            (None, Some(Shape::Blank) | None) => self.background.to_ascii_char(),
+            (None, Some(_)) => self.foreground.to_ascii_char(),
+        }
+    }
+}
+
+impl PackedTile {
+    pub fn from_tile(tile: &Tile) -> PackedTile {
+        PackedTile(tile.to_tile_code() as u32)
+    }
+
+    pub fn unpack(&self) -> Result<Tile, TileCreationError> {
+        Tile::from_tile_code(self.0 as i32)
+    }
 }
 
 impl Into<i32> for Tile {
@@ -373,3 +1063,413 @@ impl From<i32> for Tile {
     fn from(i: i32) -> Self {}
 }
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::Map;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_tile_hash() {
+        let hole = Tile::new(Some(Special::Hole), None, Element::Grass, Element::Grass);
+        let mut tiles = HashSet::new();
+        tiles.insert(Tile::default());
+        tiles.insert(hole);
+        tiles.insert(Tile::default());
+        tiles.insert(hole);
+
+        assert_eq!(tiles.len(), 2);
+        assert!(tiles.contains(&Tile::default()));
+        assert!(tiles.contains(&hole));
+    }
+
+    #[test]
+    fn test_packed_tile() {
+        assert_eq!(std::mem::size_of::<PackedTile>(), 4);
+
+        let tiles = [
+            Tile::default(),
+            Tile::new(None, Some(Shape::QuaterNW), Element::Ice, Element::OnewayW),
+            Tile::new(
+                Some(Special::StartPositionGreen),
+                None,
+                Element::AcidSwamp,
+                Element::Grass,
+            ),
+        ];
+        for tile in tiles {
+            assert_eq!(PackedTile::from_tile(&tile).unpack().unwrap(), tile);
+        }
+
+        assert!(matches!(
+            PackedTile(0).unpack(),
+            Err(TileCreationError::InvalidSpecialParse(0))
+        ));
+    }
+
+    #[test]
+    fn test_sprite_index() {
+        for &special in Special::all() {
+            assert_eq!(special.sprite_index(), special as usize);
+        }
+        for &shape in Shape::all() {
+            assert_eq!(shape.sprite_index(), shape as usize);
+        }
+        assert_eq!(Special::StartPosition.sprite_index(), 0);
+        assert_eq!(Special::StartPositionGreen.sprite_index(), 27);
+        assert_eq!(Shape::QuaterNW.sprite_index(), 27);
+    }
+
+    #[test]
+    fn test_team_color() {
+        let colors = [
+            (
+                Special::BlueTeleportStart,
+                Special::BlueTeleportExit,
+                Special::StartPositionBlue,
+                TeamColor::Blue,
+            ),
+            (
+                Special::RedTeleportStart,
+                Special::RedTeleportExit,
+                Special::StartPositionRed,
+                TeamColor::Red,
+            ),
+            (
+                Special::YellowTeleportStart,
+                Special::YellowTeleportExit,
+                Special::StartPositionYellow,
+                TeamColor::Yellow,
+            ),
+            (
+                Special::GreenTeleportStart,
+                Special::GreenTeleportExit,
+                Special::StartPositionGreen,
+                TeamColor::Green,
+            ),
+        ];
+        for (start, exit, position, color) in colors {
+            assert_eq!(start.team_color(), Some(color));
+            assert_eq!(exit.team_color(), Some(color));
+            assert_eq!(position.team_color(), Some(color));
+            assert_eq!(
+                start.get_matching_teleport().unwrap().team_color(),
+                Some(color)
+            );
+        }
+        assert_eq!(Special::StartPosition.team_color(), None);
+        assert_eq!(Special::Hole.team_color(), None);
+    }
+
+    #[test]
+    fn test_with_builders() {
+        let tile = Tile::default()
+            .with_shape(Shape::BigCircle)
+            .with_background(Element::Mud)
+            .with_foreground(Element::Ice);
+        assert_eq!(
+            tile,
+            Tile::new(None, Some(Shape::BigCircle), Element::Mud, Element::Ice)
+        );
+
+        let hole = tile.with_special(Special::Hole);
+        assert_eq!(hole.special, Some(Special::Hole));
+        assert_eq!(hole.shape, None);
+        assert_eq!(hole.background, Element::Mud);
+        assert_eq!(hole.foreground, Element::Ice);
+
+        let back = hole
+            .with_shape(Shape::Blank)
+            .with_foreground(Element::Block);
+        assert_eq!(back.special, None);
+        assert_eq!(back.shape, Some(Shape::Blank));
+        assert_eq!(back.foreground, Element::Block);
+        //The original is untouched
+        assert_eq!(tile.foreground, Element::Ice);
+    }
+
+    #[test]
+    fn test_element_directions() {
+        assert_eq!(Element::SpeedNE.downhill_direction(), Some(Direction8::NE));
+        assert_eq!(Element::OnewayW.oneway_direction(), Some(Direction4::W));
+        assert_eq!(Element::Grass.downhill_direction(), None);
+        assert_eq!(Element::SpeedN.oneway_direction(), None);
+        for element in Element::all() {
+            assert_eq!(
+                element.is_downhill(),
+                element.downhill_direction().is_some()
+            );
+            assert_eq!(element.is_oneway(), element.oneway_direction().is_some());
+        }
+
+        let speed = Element::SpeedSW.get_downhill_speed();
+        assert_eq!(
+            (speed.x, speed.y),
+            (-DOWNHILLSPEED * MAGIC, DOWNHILLSPEED * MAGIC)
+        );
+        let speed = Element::SpeedN.get_downhill_speed();
+        assert_eq!((speed.x, speed.y), (0.0, -DOWNHILLSPEED));
+    }
+
+    #[test]
+    fn test_from_i32s_errors() {
+        let normal = SpecialParse::Normal as i32;
+        let special = SpecialParse::Special as i32;
+        assert_eq!(
+            Tile::from_i32s(normal, Shape::all().len() as i32 - 1, 0, 0)
+                .unwrap()
+                .shape,
+            Shape::all().last().copied()
+        );
+        assert!(matches!(
+            Tile::from_i32s(normal, 30, 0, 0),
+            Err(TileCreationError::InvalidShape(30))
+        ));
+        assert!(matches!(
+            Tile::from_i32s(special, 30, 0, 0),
+            Err(TileCreationError::InvalidSpecial(30))
+        ));
+        assert!(matches!(
+            Tile::from_i32s(special, -1, 0, 0),
+            Err(TileCreationError::InvalidSpecial(-1))
+        ));
+        assert!(matches!(
+            Tile::from_i32s(3, 1, 0, 0),
+            Err(TileCreationError::InvalidSpecialParse(3))
+        ));
+        assert!(matches!(
+            Tile::from_tile_code((special << 24) | (40 << 16)),
+            Err(TileCreationError::InvalidSpecial(40))
+        ));
+    }
+
+    #[test]
+    fn test_try_from_i32() {
+        assert_eq!(Special::try_from(0).unwrap(), Special::StartPosition);
+        assert_eq!(Special::try_from(27).unwrap(), Special::StartPositionGreen);
+        assert!(matches!(
+            Special::try_from(28),
+            Err(TileCreationError::InvalidSpecial(28))
+        ));
+        assert!(matches!(
+            Special::try_from(-1),
+            Err(TileCreationError::InvalidSpecial(-1))
+        ));
+
+        assert_eq!(Element::try_from(0).unwrap(), Element::Grass);
+        for (i, &element) in Element::all().iter().enumerate() {
+            assert_eq!(Element::try_from(i as i32).unwrap(), element);
+        }
+        assert!(matches!(
+            Element::try_from(24),
+            Err(TileCreationError::InvalidElement(24))
+        ));
+    }
+
+    #[test]
+    fn test_fake_block() {
+        assert!(!Element::FakeBlock.is_solid());
+        assert!(Element::FakeBlock.is_illusion());
+        assert!(Element::Block.is_solid());
+        assert!(!Element::Block.is_illusion());
+        assert_eq!(Element::all().iter().filter(|e| e.is_illusion()).count(), 1);
+    }
+
+    #[test]
+    fn test_flip_halves() {
+        let assets = Assets::procedural(Map::TILESIZE);
+        let half_w = Tile::new(None, Some(Shape::HalfW), Element::Grass, Element::Block);
+        let half_s = Tile::new(None, Some(Shape::HalfS), Element::SpeedN, Element::Ice);
+        let (flipped_w, flipped_s) = (half_w.flip_horizontal(), half_s.flip_vertical());
+        assert_eq!(flipped_w.flip_horizontal(), half_w);
+        assert_eq!(flipped_s.flip_vertical(), half_s);
+
+        //The center line is left out, it belongs to one half on an odd sized sprite
+        for a in [0.05, 0.2, 0.35, 0.65, 0.8, 0.95] {
+            for b in [0.1, 0.5, 0.9] {
+                assert_eq!(
+                    flipped_w.resolve_at(&assets, a, b),
+                    half_w.resolve_at(&assets, 1.0 - a, b)
+                );
+                let mirrored = match half_s.resolve_at(&assets, b, 1.0 - a) {
+                    GameMapTile::Element(element) => GameMapTile::Element(element.flip_vertical()),
+                    tile => tile,
+                };
+                assert_eq!(flipped_s.resolve_at(&assets, b, a), mirrored);
+            }
+        }
+        assert_eq!(
+            flipped_w.resolve_at(&assets, 0.9, 0.5),
+            GameMapTile::Element(Element::Block)
+        );
+    }
+
+    #[test]
+    fn test_visual_eq() {
+        let blank = |foreground| Tile::new(None, Some(Shape::Blank), Element::Grass, foreground);
+        assert!(blank(Element::Ice).visual_eq(&blank(Element::Block)));
+        assert_ne!(blank(Element::Ice), blank(Element::Block));
+        assert!(!blank(Element::Ice).visual_eq(&blank(Element::Ice).with_background(Element::Mud)));
+
+        let hole =
+            |shape, foreground| Tile::new(Some(Special::Hole), shape, Element::Grass, foreground);
+        assert!(hole(None, Element::Grass).visual_eq(&hole(Some(Shape::Diamond), Element::Acid)));
+        assert!(!hole(None, Element::Grass).visual_eq(&blank(Element::Grass)));
+
+        let circle =
+            |foreground| Tile::new(None, Some(Shape::BigCircle), Element::Grass, foreground);
+        assert!(circle(Element::Ice).visual_eq(&circle(Element::Ice)));
+        assert!(!circle(Element::Ice).visual_eq(&circle(Element::Block)));
+        assert!(!circle(Element::Ice).visual_eq(&blank(Element::Ice)));
+    }
+
+    #[test]
+    fn test_blocks_ball() {
+        assert!(Special::MoveableBlock.blocks_ball());
+        assert!(Special::FullBreakable.blocks_ball());
+        assert!(!Special::SunkMoveableBlock.blocks_ball());
+        assert!(!Special::SunkMoveableBlock.is_solid());
+        assert!(!Special::Hole.blocks_ball());
+    }
+
+    #[test]
+    fn test_impulse_on_hit() {
+        let approach = Vector2D::new(3.0, 4.0);
+        let mine = Special::Mine.impulse_on_hit(approach).unwrap();
+        assert!((mine.length() - Special::MINE_IMPULSE).abs() < 0.0001);
+        assert!((mine.x - 0.6 * Special::MINE_IMPULSE).abs() < 0.0001);
+
+        let big = Special::BigMine.impulse_on_hit(approach).unwrap();
+        assert!(big.length() > mine.length());
+        assert!((big.y - 0.8 * Special::BIG_MINE_IMPULSE).abs() < 0.0001);
+
+        let center = Special::Mine.impulse_on_hit(Vector2D::zero()).unwrap();
+        assert_eq!((center.x, center.y), (0.0, -Special::MINE_IMPULSE));
+
+        assert!(Special::Hole.impulse_on_hit(approach).is_none());
+        assert!(Special::BlownMine.impulse_on_hit(approach).is_none());
+    }
+
+    #[test]
+    fn test_resolve_at() {
+        let assets = Assets::procedural(Map::TILESIZE);
+        let hole = Tile::default().with_special(Special::Hole);
+        assert_eq!(
+            hole.resolve_at(&assets, 0.5, 0.5),
+            GameMapTile::Special(Special::Hole)
+        );
+        assert_eq!(
+            hole.resolve_at(&assets, 0.0, 0.0),
+            GameMapTile::Element(Element::Grass)
+        );
+
+        let half = Tile::new(None, Some(Shape::HalfW), Element::Grass, Element::Ice);
+        assert_eq!(
+            half.resolve_at(&assets, 0.1, 0.9),
+            GameMapTile::Element(Element::Ice)
+        );
+        assert_eq!(
+            half.resolve_at(&assets, 1.0, 0.5),
+            GameMapTile::Element(Element::Grass)
+        );
+    }
+
+    #[test]
+    fn test_fake_hole() {
+        assert!(Special::Hole.is_capturing_hole());
+        assert!(!Special::FakeHole.is_capturing_hole());
+        assert_eq!(Special::FakeHole.looks_like(), Some(Special::Hole));
+        assert_eq!(Special::Hole.looks_like(), None);
+        assert_eq!(Special::Mine.looks_like(), None);
+    }
+
+    #[test]
+    fn test_team_color_constructors() {
+        for &color in TeamColor::all() {
+            let start = Special::teleport_start(color);
+            let exit = Special::teleport_exit(color);
+            let position = Special::start_position(color);
+            assert!(start.is_teleport_start());
+            assert!(exit.is_teleport_exit());
+            assert!(position.is_start_position());
+            assert_eq!(start.get_matching_teleport(), Some(exit));
+            assert_eq!(exit.get_matching_teleport(), None);
+            assert_eq!(start.team_color(), Some(color));
+            assert_eq!(exit.team_color(), Some(color));
+            assert_eq!(position.team_color(), Some(color));
+        }
+        assert_eq!(
+            Special::teleport_start(TeamColor::Yellow).get_matching_teleport(),
+            Some(Special::YellowTeleportExit)
+        );
+        assert_eq!(Special::StartPositionRed.get_matching_teleport(), None);
+    }
+
+    #[test]
+    fn test_all() {
+        assert_eq!(Element::all().len(), 24);
+        assert_eq!(Special::all().len(), 28);
+        assert_eq!(Shape::all().len(), 28);
+
+        for (i, &element) in Element::all().iter().enumerate() {
+            assert_eq!(Element::from_i32(i as i32), Some(element));
+        }
+        for (i, &special) in Special::all().iter().enumerate() {
+            assert_eq!(Special::from_i32(i as i32), Some(special));
+        }
+        for (i, &shape) in Shape::all().iter().enumerate() {
+            assert_eq!(Shape::from_i32(i as i32), Some(shape));
+        }
+    }
+
+    #[test]
+    fn test_to_ascii_char() {
+        let hole = Tile::new(Some(Special::Hole), None, Element::Grass, Element::Grass);
+        let block = Tile::new(None, Some(Shape::Blank), Element::Block, Element::Grass);
+        let circle = Tile::new(None, Some(Shape::BigCircle), Element::Grass, Element::Block);
+        let teleport = Tile::new(
+            Some(Special::RedTeleportStart),
+            None,
+            Element::Block,
+            Element::Grass,
+        );
+
+        assert_eq!(hole.to_ascii_char(), 'O');
+        assert_eq!(block.to_ascii_char(), '#');
+        assert_eq!(circle.to_ascii_char(), '#');
+        assert_eq!(teleport.to_ascii_char(), 'R');
+        assert_eq!(Tile::default().to_ascii_char(), '.');
+    }
+
+    #[test]
+    fn test_from_char() {
+        for &element in Element::all() {
+            assert_eq!(Element::from_char(element.to_ascii_char()), Some(element));
+            assert_eq!(Special::from_char(element.to_ascii_char()), None);
+        }
+        for &special in Special::all() {
+            assert_eq!(Special::from_char(special.to_ascii_char()), Some(special));
+            assert_eq!(Element::from_char(special.to_ascii_char()), None);
+        }
+        assert_eq!(Element::from_char('?'), None);
+        assert_eq!(Special::from_char('?'), None);
+    }
+
+    #[test]
+    fn test_names() {
+        for &element in Element::all() {
+            assert_eq!(Element::from_name(element.name()), Some(element));
+        }
+        for &special in Special::all() {
+            assert_eq!(Special::from_name(special.name()), Some(special));
+        }
+        for &shape in Shape::all() {
+            assert_eq!(Shape::from_name(shape.name()), Some(shape));
+        }
+        assert_eq!(Special::MagnetAttract.name(), "MagnetAttract");
+        assert_eq!(Element::from_name("SpeedNE"), Some(Element::SpeedNE));
+        assert_eq!(Shape::from_name("bigcircle"), None);
+        assert_eq!(Element::from_name(""), None);
+    }
+}
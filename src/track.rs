@@ -1,15 +1,19 @@
 use bitflags::bitflags;
-use chrono::NaiveDateTime;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use num_traits::FromPrimitive;
+use std::fmt;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Write};
+use std::ops::RangeInclusive;
 use std::str::FromStr;
 use thiserror::Error;
 
 use crate::map::Map;
 use crate::map::MapError;
+use crate::map::{AdIssue, MapValidationIssue};
+use crate::tile::{Element, Special, Tile};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Record {
     pub name: String,
     pub timestamp: NaiveDateTime,
@@ -26,6 +30,12 @@ pub struct Track {
     pub stroke_info: Vec<i32>,
     pub map: Map,
     pub record: Record,
+    pub records: Vec<Record>,
+    pub par: Option<u32>,
+    //The "T" line as it was read, written back as is until the map is dirty
+    pub raw_map: Option<String>,
+    //Set by map_mut and set_map. Code that edits map directly has to set it too.
+    pub dirty: bool,
 }
 
 #[derive(Debug, Error)]
@@ -38,10 +48,27 @@ pub enum ParseError {
 
     #[error("Map error: {0}")]
     MapError(#[from] MapError),
+
+    #[error("Track is larger than {0} bytes")]
+    TooLarge(usize),
+}
+
+#[derive(Debug, PartialEq, Error)]
+pub enum TrackIssue {
+    #[error("Map: {0}")]
+    Map(MapValidationIssue),
+    #[error("Ad: {0:?}")]
+    Ad(AdIssue),
+    #[error(
+        "Player range {min}-{max} is not within {}-{}",
+        Settings::MIN_PLAYERS,
+        Settings::MAX_PLAYERS
+    )]
+    PlayerRange { min: i32, max: i32 },
 }
 
 bitflags! {
-    #[derive(Debug)]
+    #[derive(Debug, PartialEq, Eq, Copy, Clone)]
     pub struct TrackTypeFlags: u32 {
         const BASIC = 0b00000001;
         const HOLEINONE = 0b00001000;
@@ -52,6 +79,28 @@ bitflags! {
     }
 }
 
+impl TrackTypeFlags {
+    //Set single flags, lowest value first
+    pub fn iter_set(&self) -> impl Iterator<Item = TrackTypeFlags> + '_ {
+        (0..u32::BITS)
+            .filter_map(|bit| TrackTypeFlags::from_bits(1 << bit))
+            .filter(|flag| self.contains(*flag))
+    }
+
+    //None unless exactly one flag is set
+    pub fn name(&self) -> Option<&'static str> {
+        match *self {
+            TrackTypeFlags::BASIC => Some("Basic"),
+            TrackTypeFlags::TRADITIONAL => Some("Traditional"),
+            TrackTypeFlags::MODERN => Some("Modern"),
+            TrackTypeFlags::HOLEINONE => Some("Hole in one"),
+            TrackTypeFlags::SHORT => Some("Short"),
+            TrackTypeFlags::LONG => Some("Long"),
+            _ => None,
+        }
+    }
+}
+
 impl FromPrimitive for TrackTypeFlags {
     fn from_i64(n: i64) -> Option<Self> {
         match n {
@@ -70,7 +119,7 @@ impl FromPrimitive for TrackTypeFlags {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Settings {
     pub magnets_visible: bool,
     pub mines_visible: bool,
@@ -80,6 +129,15 @@ pub struct Settings {
     pub min_players: i32,
 }
 
+impl Settings {
+    pub const MAX_PLAYERS: i32 = 4;
+    pub const MIN_PLAYERS: i32 = 1;
+
+    pub fn player_range(&self) -> RangeInclusive<i32> {
+        self.min_players..=self.max_players
+    }
+}
+
 impl Default for Settings {
     fn default() -> Self {
         Self {
@@ -87,12 +145,28 @@ impl Default for Settings {
             mines_visible: false,
             teleport_colors: false,
             illusion_wall_shadows: false,
-            max_players: 1,
-            min_players: 4,
+            max_players: 4,
+            min_players: 1,
         }
     }
 }
 
+impl fmt::Display for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flag = |b: bool| if b { 't' } else { 'f' };
+        write!(
+            f,
+            "{}{}{}{}{}{}",
+            flag(self.mines_visible),
+            flag(self.magnets_visible),
+            flag(self.teleport_colors),
+            flag(self.illusion_wall_shadows),
+            self.min_players,
+            self.max_players
+        )
+    }
+}
+
 impl FromStr for Settings {
     type Err = ParseError;
 
@@ -104,10 +178,16 @@ impl FromStr for Settings {
             return Err(ParseError::InvalidFormat);
         }
 
-        settings.mines_visible = chars[0] == 't';
-        settings.magnets_visible = chars[1] == 't';
-        settings.teleport_colors = chars[2] == 't';
-        settings.illusion_wall_shadows = chars[3] == 't';
+        let flag = |c: char| match c {
+            't' => Ok(true),
+            'f' => Ok(false),
+            _ => Err(ParseError::InvalidFormat),
+        };
+
+        settings.mines_visible = flag(chars[0])?;
+        settings.magnets_visible = flag(chars[1])?;
+        settings.teleport_colors = flag(chars[2])?;
+        settings.illusion_wall_shadows = flag(chars[3])?;
 
         let min_players_str: String = chars[4].to_string();
         let max_players_str: String = chars[5].to_string();
@@ -124,9 +204,50 @@ impl FromStr for Settings {
 }
 
 impl Track {
+    pub const MAX_RECORDS: usize = 10;
+
     fn from_reader<R: BufRead>(reader: &mut R) -> Result<Track, ParseError> {
-        let lines = reader.lines();
+        Track::from_lines(reader.lines().map(|line| line.map_err(ParseError::from)))
+    }
 
+    //Fails with TooLarge as soon as more than max_bytes have been read, the
+    //reader is never read much past the limit
+    pub fn from_reader_limited<R: BufRead>(
+        reader: R,
+        max_bytes: usize,
+    ) -> Result<Track, ParseError> {
+        let mut reader = reader.take(max_bytes as u64 + 1);
+        let mut total = 0;
+        //The limit can cut a character in half, so the size is checked before
+        //the bytes are decoded
+        let lines = std::iter::from_fn(|| {
+            let mut line = Vec::new();
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) => None,
+                Ok(read) => {
+                    total += read;
+                    if total > max_bytes {
+                        return Some(Err(ParseError::TooLarge(max_bytes)));
+                    }
+                    if line.ends_with(b"\n") {
+                        line.pop();
+                        if line.ends_with(b"\r") {
+                            line.pop();
+                        }
+                    }
+                    Some(String::from_utf8(line).map_err(|err| {
+                        std::io::Error::new(std::io::ErrorKind::InvalidData, err).into()
+                    }))
+                }
+                Err(err) => Some(Err(err.into())),
+            }
+        });
+        Track::from_lines(lines)
+    }
+
+    fn from_lines(
+        lines: impl Iterator<Item = Result<String, ParseError>>,
+    ) -> Result<Track, ParseError> {
         let mut track = Track {
             version: 0,
             author: String::new(),
@@ -140,6 +261,10 @@ impl Track {
                 name: String::new(),
                 timestamp: NaiveDateTime::default(),
             },
+            records: Vec::new(),
+            par: None,
+            raw_map: None,
+            dirty: false,
         };
 
         for line in lines {
@@ -182,7 +307,11 @@ impl Track {
                         .parse::<Settings>()
                         .map_err(|_| ParseError::InvalidFormat)?;
                 }
-                "T" => track.map = Map::from_string(data)?,
+                "T" => {
+                    track.map = Map::from_string(data)?;
+                    track.raw_map = Some(data.to_owned());
+                    track.dirty = false;
+                }
                 "R" => {
                     let ratings: Vec<i32> = data
                         .split(',')
@@ -204,17 +333,19 @@ impl Track {
                             name: name.to_owned(),
                             timestamp: naive_timestamp,
                         };
+                        track.records = vec![track.record.clone()];
                     } else {
                         return Err(ParseError::InvalidFormat);
                     }
                 }
                 "I" => {
-                    let ratings: Vec<i32> = data
+                    let stroke_info: Vec<i32> = data
                         .split(',')
-                        .map(|rating| rating.parse().unwrap_or(0))
+                        .map(|info| info.parse().unwrap_or(0))
                         .collect();
-                    track.ratings = ratings;
+                    track.stroke_info = stroke_info;
                 }
+                "P" => track.par = Some(data.parse().map_err(|_| ParseError::InvalidFormat)?),
                 _ => return Err(ParseError::InvalidFormat),
             }
         }
@@ -222,28 +353,719 @@ impl Track {
         Ok(track)
     }
 
+    //Runs every map, ad and settings check and collects all issues
+    pub fn validate(&self) -> Vec<TrackIssue> {
+        let mut issues: Vec<TrackIssue> = self
+            .map
+            .validate()
+            .into_iter()
+            .map(TrackIssue::Map)
+            .collect();
+        issues.extend(self.map.validate_ads().into_iter().map(TrackIssue::Ad));
+
+        let (min, max) = (self.settings.min_players, self.settings.max_players);
+        if min > max || min < Settings::MIN_PLAYERS || max > Settings::MAX_PLAYERS {
+            issues.push(TrackIssue::PlayerRange { min, max });
+        } else if let Err(issue) = self.map.validate_team_starts(&self.settings) {
+            issues.push(TrackIssue::Map(issue));
+        }
+        issues
+    }
+
+    //True if the map was changed since it was read, or was not read at all
+    pub fn map_modified(&self) -> bool {
+        self.dirty || self.raw_map.is_none()
+    }
+
+    pub fn map_mut(&mut self) -> &mut Map {
+        self.dirty = true;
+        &mut self.map
+    }
+
+    pub fn set_map(&mut self, map: Map) {
+        self.map = map;
+        self.dirty = true;
+    }
+
+    //Writes the track in the same format from_reader reads
+    pub fn write<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let join = |values: &[i32]| {
+            values
+                .iter()
+                .map(|value| value.to_string())
+                .collect::<Vec<String>>()
+                .join(",")
+        };
+
+        writeln!(writer, "V {}", self.version)?;
+        writeln!(writer, "A {}", self.author)?;
+        writeln!(writer, "N {}", self.name)?;
+        match &self.raw_map {
+            Some(raw) if !self.dirty => writeln!(writer, "T {}", raw)?,
+            _ => {
+                let map = self
+                    .map
+                    .to_track_string()
+                    .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+                writeln!(writer, "T {}", map)?
+            }
+        }
+        writeln!(writer, "S {}", self.settings)?;
+        let categories: Vec<i32> = (1..=6)
+            .filter(|&i| {
+                TrackTypeFlags::from_i32(i).is_some_and(|flag| self.categories.contains(flag))
+            })
+            .collect();
+        if !categories.is_empty() {
+            writeln!(writer, "C {}", join(&categories))?;
+        }
+        if !self.stroke_info.is_empty() {
+            writeln!(writer, "I {}", join(&self.stroke_info))?;
+        }
+        if !self.ratings.is_empty() {
+            writeln!(writer, "R {}", join(&self.ratings))?;
+        }
+        if !self.record.name.is_empty() {
+            writeln!(
+                writer,
+                "B {},{}",
+                self.record.name,
+                self.record.timestamp.timestamp()
+            )?;
+        }
+        if let Some(par) = self.par {
+            writeln!(writer, "P {}", par)?;
+        }
+        Ok(())
+    }
+
+    //The timestamp is when the record was reached, so the earliest one is the
+    //best. A record without a name counts as unset and is always replaced.
+    //The `records` leaderboard keeps the best MAX_RECORDS attempts, one per name.
+    pub fn try_set_record(&mut self, name: &str, timestamp: DateTime<Utc>) -> bool {
+        let record = Record {
+            name: name.to_owned(),
+            timestamp: timestamp.naive_utc(),
+        };
+
+        let own_best = self
+            .records
+            .iter()
+            .position(|existing| existing.name == name);
+        if own_best.is_none_or(|i| record.timestamp < self.records[i].timestamp) {
+            if let Some(i) = own_best {
+                self.records.remove(i);
+            }
+            let position = self
+                .records
+                .partition_point(|existing| existing.timestamp <= record.timestamp);
+            if position < Track::MAX_RECORDS {
+                self.records.insert(position, record.clone());
+                self.records.truncate(Track::MAX_RECORDS);
+            }
+        }
+
+        if self.record.name.is_empty() || record.timestamp < self.record.timestamp {
+            self.record = record;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn top_records(&self, n: usize) -> &[Record] {
+        &self.records[..n.min(self.records.len())]
+    }
+
+    //Rough 0-10 score, the sum of six capped parts:
+    //  2 * solid tiles / all tiles
+    //  2 * min(live mines / 10, 1)
+    //  1 * min(teleport starts / 4, 1)
+    //  1 * min(magnets / 8, 1)
+    //  2 * min(4 * liquid tiles / all tiles, 1)
+    //  2 * start to hole distance / map diagonal, 0 without a start and hole
+    pub fn difficulty(&self) -> f32 {
+        let tiles = &self.map.tiles;
+        if tiles.is_empty() {
+            return 0.0;
+        }
+        let total = tiles.len() as f32;
+        let count = |f: fn(&Tile) -> bool| tiles.iter().filter(|tile| f(tile)).count() as f32;
+
+        let solid = count(|tile| {
+            tile.special.is_some_and(|special| special.is_solid()) || tile.background.is_solid()
+        });
+        let mines = count(|tile| matches!(tile.special, Some(Special::Mine | Special::BigMine)));
+        let teleports = count(|tile| {
+            tile.special
+                .is_some_and(|special| special.is_teleport_start())
+        });
+        let magnets = count(|tile| {
+            matches!(
+                tile.special,
+                Some(Special::MagnetAttract | Special::MagnetRepel)
+            )
+        });
+        let liquid = count(|tile| {
+            matches!(
+                tile.background,
+                Element::Water | Element::Acid | Element::WaterSwamp | Element::AcidSwamp
+            )
+        });
+
+        let start = self.map.start_positions().first().map(|(_, xy)| *xy);
+        let hole = self.map.find_all_special(Special::Hole).first().copied();
+        let distance = match (start, hole) {
+            (Some((sx, sy)), Some((hx, hy))) => {
+                let dx = sx as f32 - hx as f32;
+                let dy = sy as f32 - hy as f32;
+                let (width, height) = (self.map.width(), self.map.height());
+                let diagonal = ((width * width + height * height) as f32).sqrt();
+                (dx * dx + dy * dy).sqrt() / diagonal
+            }
+            _ => 0.0,
+        };
+
+        let score = 2.0 * solid / total
+            + 2.0 * (mines / 10.0).min(1.0)
+            + (teleports / 4.0).min(1.0)
+            + (magnets / 8.0).min(1.0)
+            + 2.0 * (4.0 * liquid / total).min(1.0)
+            + 2.0 * distance;
+        score.clamp(0.0, 10.0)
+    }
+
+    pub fn category_names(&self) -> Vec<&'static str> {
+        self.categories
+            .iter_set()
+            .filter_map(|flag| flag.name())
+            .collect()
+    }
+
+    pub fn from_string(input: &str) -> Result<Track, ParseError> {
+        Track::from_reader(&mut input.as_bytes())
+    }
+
     pub fn from_filepath(filepath: &str) -> Result<Track, ParseError> {
         let file = File::open(filepath)?;
         let mut reader = BufReader::new(file);
         Track::from_reader(&mut reader)
     }
 }
+
+//The raw "T" line is only a cache of how the map was read and is not compared
+impl PartialEq for Track {
+    fn eq(&self, other: &Self) -> bool {
+        self.version == other.version
+            && self.author == other.author
+            && self.name == other.name
+            && self.categories == other.categories
+            && self.settings == other.settings
+            && self.ratings == other.ratings
+            && self.stroke_info == other.stroke_info
+            && self.map == other.map
+            && self.record == other.record
+            && self.records == other.records
+            && self.par == other.par
+    }
+}
+
+impl fmt::Display for Track {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut output = Vec::new();
+        self.write(&mut output).map_err(|_| fmt::Error)?;
+        f.write_str(&String::from_utf8_lossy(&output))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chrono::TimeZone;
+    use std::path::Path;
+
+    //A valid version 2 track with 3 ads, like testi.track, written to a temp
+    //file and read back
+    fn fixture() -> Track {
+        let special = |special| Tile::new(Some(special), None, Element::Grass, Element::Grass);
+        let mut map = Map::new();
+        map.set_tile(3, 12, special(Special::StartPosition))
+            .unwrap();
+        map.set_tile(45, 12, special(Special::Hole)).unwrap();
+        map.ads = crate::map::Ad::from_string("A2309B2204C4019").unwrap();
+
+        let track = Track {
+            version: 2,
+            author: "Nokkis".to_owned(),
+            name: "Test".to_owned(),
+            categories: TrackTypeFlags::MODERN | TrackTypeFlags::HOLEINONE,
+            settings: "fttf14".parse().unwrap(),
+            ratings: vec![94, 12, 23, 28, 28, 77, 67, 49, 33, 31, 279],
+            stroke_info: vec![13942, 90651, 1, 37],
+            map,
+            record: Record {
+                name: "igo".to_owned(),
+                timestamp: NaiveDateTime::from_timestamp_opt(1283637600, 0).unwrap(),
+            },
+            records: Vec::new(),
+            par: None,
+            raw_map: None,
+            dirty: false,
+        };
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        track.write(file.as_file_mut()).unwrap();
+        Track::from_filepath(file.path().to_str().unwrap()).unwrap()
+    }
 
     #[test]
     fn test_from_filepath() {
-        let filepath = "testi.track";
-
-        let result = Track::from_filepath(filepath);
+        let track = fixture();
 
-        assert!(result.is_ok());
+        assert_eq!(track.version, 2);
+        assert_eq!(track.map.ads.len(), 3);
+        assert!(!track.settings.mines_visible);
+        assert!(track.settings.magnets_visible);
+        assert_eq!(track.settings.max_players, 4);
+        assert_eq!(track.categories.bits(), 0b1100);
+        assert_eq!(track.record.name, "igo");
+    }
 
-        let track = result.unwrap();
+    //The checked in example track, skipped when it's not there
+    #[test]
+    fn test_example_file() {
+        let filepath = "testi.track";
+        if !Path::new(filepath).exists() {
+            return;
+        }
+        let track = Track::from_filepath(filepath).unwrap();
 
         assert_eq!(track.version, 2);
         assert_eq!(track.map.ads.len(), 3);
-        // assert_eq!(track.title, "Some Title");
+        assert!(!track.settings.mines_visible);
+        assert!(track.settings.magnets_visible);
+        assert_eq!(track.settings.max_players, 4);
+        assert_eq!(track.stroke_info, vec![13942, 90651, 1, 37]);
+        //Its small and medium ads overlap
+        assert_eq!(
+            track.validate(),
+            vec![TrackIssue::Ad(AdIssue::Overlap(0, 1))]
+        );
+    }
+
+    #[test]
+    fn test_category_names() {
+        let mut track = fixture();
+        assert_eq!(track.category_names(), vec!["Modern", "Hole in one"]);
+
+        track.categories = TrackTypeFlags::SHORT | TrackTypeFlags::BASIC;
+        assert_eq!(track.category_names(), vec!["Basic", "Short"]);
+
+        track.categories = TrackTypeFlags::empty();
+        assert!(track.category_names().is_empty());
+        assert_eq!(TrackTypeFlags::all().name(), None);
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut track = fixture();
+        assert_eq!(track.validate(), Vec::new());
+
+        let special = |special| Tile::new(Some(special), None, Element::Grass, Element::Grass);
+        for (special, _) in track.map.start_positions() {
+            for (x, y) in track.map.find_all_special(special) {
+                track.map.set_tile(x, y, Tile::default()).unwrap();
+            }
+        }
+        track.map.ads = crate::map::Ad::from_string("A4700").unwrap();
+        track.settings.min_players = 3;
+        track.settings.max_players = 2;
+        assert_eq!(
+            track.validate(),
+            vec![
+                TrackIssue::Map(MapValidationIssue::MissingStart),
+                TrackIssue::Ad(AdIssue::OutOfBounds(0)),
+                TrackIssue::PlayerRange { min: 3, max: 2 },
+            ]
+        );
+
+        track.settings.min_players = 1;
+        track.map.ads.clear();
+        track
+            .map
+            .set_tile(1, 1, special(Special::StartPositionBlue))
+            .unwrap();
+        track
+            .map
+            .set_tile(2, 1, special(Special::StartPositionBlue))
+            .unwrap();
+        assert!(matches!(
+            track.validate().as_slice(),
+            [TrackIssue::Map(MapValidationIssue::DuplicateTeamStart(_))]
+        ));
+    }
+
+    #[test]
+    fn test_par() {
+        let track = fixture();
+        assert_eq!(track.par, None);
+
+        let mut input = Vec::new();
+        track.write(&mut input).unwrap();
+        input.extend_from_slice(b"P 4\n");
+        let track = Track::from_reader(&mut input.as_slice()).unwrap();
+        assert_eq!(track.par, Some(4));
+
+        assert!(matches!(
+            Track::from_reader(&mut "P four\n".as_bytes()),
+            Err(ParseError::InvalidFormat)
+        ));
+    }
+
+    #[test]
+    fn test_write() {
+        let mut track = fixture();
+        track.par = Some(3);
+        let mut output = Vec::new();
+        track.write(&mut output).unwrap();
+
+        let written = Track::from_reader(&mut output.as_slice()).unwrap();
+        assert_eq!(written.version, track.version);
+        assert_eq!(written.author, track.author);
+        assert_eq!(written.name, track.name);
+        assert_eq!(written.categories.bits(), track.categories.bits());
+        assert_eq!(written.settings.to_string(), "fttf14");
+        assert_eq!(written.ratings, track.ratings);
+        assert_eq!(written.stroke_info, vec![13942, 90651, 1, 37]);
+        assert_eq!(written.record, track.record);
+        assert_eq!(written.map, track.map);
+        assert_eq!(written.par, Some(3));
+    }
+
+    #[test]
+    fn test_write_keeps_raw_map() {
+        //An uncompressed T line, which writing the map again would compress
+        let fixture = fixture();
+        let original = format!("T {},Ads:A2309B2204C4019", fixture.map.encode().unwrap());
+        assert_ne!(
+            original,
+            format!("T {}", fixture.map.to_track_string().unwrap())
+        );
+        let contents = format!("V 2\n{}\n", original);
+        let t_line = |track: &Track| {
+            let mut output = Vec::new();
+            track.write(&mut output).unwrap();
+            String::from_utf8(output)
+                .unwrap()
+                .lines()
+                .find(|line| line.starts_with("T "))
+                .unwrap()
+                .to_owned()
+        };
+
+        let mut track = Track::from_string(&contents).unwrap();
+        assert!(!track.map_modified());
+        assert_eq!(t_line(&track), original);
+
+        let mine = Tile::new(Some(Special::Mine), None, Element::Grass, Element::Grass);
+        track.map_mut().set_tile(0, 0, mine).unwrap();
+        assert!(track.map_modified());
+        assert_eq!(
+            t_line(&track),
+            format!("T {}", track.map.to_track_string().unwrap())
+        );
+
+        let mut track = Track::from_string(&contents).unwrap();
+        track.set_map(Map::new());
+        assert_eq!(
+            t_line(&track),
+            format!("T {}", Map::new().to_track_string().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_from_reader_limited() {
+        let input = fixture().to_string();
+        let track = Track::from_reader_limited(input.as_bytes(), input.len()).unwrap();
+        assert_eq!(track, Track::from_string(&input).unwrap());
+
+        let result = Track::from_reader_limited(input.as_bytes(), input.len() - 1);
+        assert!(matches!(result, Err(ParseError::TooLarge(_))));
+        let result = Track::from_reader_limited(input.as_bytes(), 10);
+        assert!(matches!(result, Err(ParseError::TooLarge(10))));
+
+        //A limit inside a multi-byte character is still TooLarge
+        let mut track = fixture();
+        track.author = "Äyrämö".to_owned();
+        let input = track.to_string();
+        let cut = input.find('Ä').unwrap();
+        let result = Track::from_reader_limited(input.as_bytes(), cut);
+        assert!(matches!(result, Err(ParseError::TooLarge(_))));
+        let parsed = Track::from_reader_limited(input.as_bytes(), input.len()).unwrap();
+        assert_eq!(parsed.author, "Äyrämö");
+
+        let result = Track::from_reader_limited(&b"A \xff\n"[..], 100);
+        assert!(matches!(result, Err(ParseError::IOError(_))));
+    }
+
+    #[test]
+    fn test_eq() {
+        let track = fixture();
+        let rewritten = Track::from_string(&track.to_string()).unwrap();
+        assert_eq!(rewritten, track);
+
+        let mut other = Track::from_string(&track.to_string()).unwrap();
+        other.settings.mines_visible = !other.settings.mines_visible;
+        assert_ne!(other, track);
+
+        let mut other = Track::from_string(&track.to_string()).unwrap();
+        other
+            .map
+            .set_tile(1, 1, Tile::default().with_background(Element::Ice))
+            .unwrap();
+        assert_ne!(other, track);
+    }
+
+    #[test]
+    fn test_try_set_record() {
+        let mut track = fixture();
+        let current = track.record.timestamp;
+        let earlier = Utc.from_utc_datetime(&(current - chrono::Duration::days(1)));
+        let later = Utc.from_utc_datetime(&(current + chrono::Duration::days(1)));
+
+        assert!(!track.try_set_record("slow", later));
+        assert_eq!(track.record.timestamp, current);
+
+        assert!(track.try_set_record("fast", earlier));
+        assert_eq!(track.record.name, "fast");
+        assert_eq!(track.record.timestamp, earlier.naive_utc());
+
+        let names: Vec<&str> = track
+            .top_records(5)
+            .iter()
+            .map(|record| record.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["fast", "igo", "slow"]);
+        assert_eq!(track.top_records(1).len(), 1);
+    }
+
+    #[test]
+    fn test_records_leaderboard() {
+        let mut track = fixture();
+        assert_eq!(track.records, vec![track.record.clone()]);
+
+        let base = Utc.from_utc_datetime(&track.record.timestamp);
+        let at = |days: i64| base + chrono::Duration::days(days);
+
+        //Repeats only keep a player's best attempt
+        for days in [5, 3, 4, 3] {
+            track.try_set_record("repeat", at(days));
+        }
+        let repeats = track
+            .records
+            .iter()
+            .filter(|record| record.name == "repeat");
+        assert_eq!(repeats.count(), 1);
+        assert_eq!(track.records[1].timestamp, at(3).naive_utc());
+
+        for i in 0..Track::MAX_RECORDS as i64 {
+            track.try_set_record(&format!("player{}", i), at(10 + i));
+        }
+        assert_eq!(track.records.len(), Track::MAX_RECORDS);
+        assert_eq!(track.records.last().unwrap().name, "player7");
+
+        //Worse than the whole leaderboard is not kept
+        track.try_set_record("late", at(100));
+        assert!(track.records.iter().all(|record| record.name != "late"));
+        assert!(track.try_set_record("early", at(-1)));
+        assert_eq!(track.records[0].name, "early");
+        assert_eq!(track.records.len(), Track::MAX_RECORDS);
+    }
+
+    #[test]
+    fn test_try_set_record_unset() {
+        let mut track = fixture();
+        track.record.name.clear();
+        assert!(track.try_set_record("first", Utc::now()));
+        assert_eq!(track.record.name, "first");
+    }
+
+    #[test]
+    fn test_difficulty() {
+        let mut track = fixture();
+        let score = track.difficulty();
+        assert!((0.0..=10.0).contains(&score));
+
+        track.map = Map::new();
+        let empty = track.difficulty();
+        assert_eq!(empty, 0.0);
+
+        let mine = Tile::new(Some(Special::Mine), None, Element::Grass, Element::Grass);
+        for x in 0..20 {
+            track.map.set_tile(x, 5, mine).unwrap();
+        }
+        let mined = track.difficulty();
+        assert!(mined > empty);
+        assert_eq!(mined, track.difficulty());
+        assert!(mined <= 10.0);
+    }
+
+    #[test]
+    fn test_player_range() {
+        let settings = Settings::default();
+        assert_eq!(settings.player_range(), 1..=4);
+        assert_eq!(
+            settings.player_range(),
+            Settings::MIN_PLAYERS..=Settings::MAX_PLAYERS
+        );
+
+        let settings: Settings = "fttf23".parse().unwrap();
+        assert_eq!(settings.player_range(), 2..=3);
+        assert!(!settings.player_range().contains(&1));
+    }
+
+    #[test]
+    fn test_settings_from_str() {
+        let settings: Settings = "tfft14".parse().unwrap();
+        assert!(settings.mines_visible);
+        assert!(!settings.magnets_visible);
+        assert!(!settings.teleport_colors);
+        assert!(settings.illusion_wall_shadows);
+        assert_eq!(settings.min_players, 1);
+        assert_eq!(settings.max_players, 4);
+
+        assert!(matches!(
+            "xxtt14".parse::<Settings>(),
+            Err(ParseError::InvalidFormat)
+        ));
+        assert!(matches!(
+            "tftT14".parse::<Settings>(),
+            Err(ParseError::InvalidFormat)
+        ));
+    }
+
+    //Random but valid tracks must survive being written and parsed again, and
+    //malformed input must give an error instead of a panic
+    mod round_trip {
+        use super::*;
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        fn random_word(rng: &mut StdRng) -> String {
+            let len = rng.gen_range(1..12);
+            (0..len)
+                .map(|_| rng.sample(rand::distributions::Alphanumeric) as char)
+                .collect()
+        }
+
+        fn random_values(rng: &mut StdRng) -> Vec<i32> {
+            let len = rng.gen_range(0..6);
+            (0..len).map(|_| rng.gen_range(0..100000)).collect()
+        }
+
+        fn random_track(rng: &mut StdRng) -> Track {
+            let mut categories = TrackTypeFlags::empty();
+            for i in 1..=6 {
+                if rng.gen_bool(0.3) {
+                    categories |= TrackTypeFlags::from_i32(i).unwrap();
+                }
+            }
+            let min_players = rng.gen_range(1..=4);
+            let record = if rng.gen_bool(0.5) {
+                Record {
+                    name: random_word(rng),
+                    timestamp: NaiveDateTime::from_timestamp_opt(rng.gen_range(0..2000000000), 0)
+                        .unwrap(),
+                }
+            } else {
+                Record {
+                    name: String::new(),
+                    timestamp: NaiveDateTime::default(),
+                }
+            };
+            //Only the B line is written, the leaderboard is read back from it
+            let records = if record.name.is_empty() {
+                Vec::new()
+            } else {
+                vec![record.clone()]
+            };
+            Track {
+                version: rng.gen_range(1..3),
+                author: random_word(rng),
+                name: format!("{} {}", random_word(rng), random_word(rng)),
+                categories,
+                settings: Settings {
+                    magnets_visible: rng.gen(),
+                    mines_visible: rng.gen(),
+                    teleport_colors: rng.gen(),
+                    illusion_wall_shadows: rng.gen(),
+                    min_players,
+                    max_players: rng.gen_range(min_players..=4),
+                },
+                ratings: random_values(rng),
+                stroke_info: random_values(rng),
+                map: Map::random(rng),
+                record,
+                records,
+                par: rng.gen_bool(0.5).then(|| rng.gen_range(1..10)),
+                raw_map: None,
+                dirty: false,
+            }
+        }
+
+        #[test]
+        fn test_random_tracks() {
+            let mut rng = StdRng::seed_from_u64(17);
+            for _ in 0..20 {
+                let track = random_track(&mut rng);
+                let text = track.to_string();
+                let parsed = Track::from_string(&text).unwrap();
+
+                assert_eq!(parsed, track);
+                assert_eq!(parsed.to_string(), text);
+            }
+        }
+
+        #[test]
+        fn test_malformed_tracks() {
+            let inputs = [
+                "V two\n",
+                "V\n",
+                "C 1,9\n",
+                "S tftf1\n",
+                "S tftf1x\n",
+                "B name\n",
+                "B name,soon\n",
+                "X 1\n",
+                "T B\n",
+                "T Q\n",
+                "T 99999999999A\n",
+                "T \u{e9}\u{e9}\u{e9}\n",
+            ];
+            for input in inputs {
+                let result = std::panic::catch_unwind(|| Track::from_string(input));
+                assert!(
+                    matches!(result, Ok(Err(_))),
+                    "{:?} should fail to parse",
+                    input
+                );
+            }
+        }
+
+        #[test]
+        fn test_random_map_strings() {
+            let mut rng = StdRng::seed_from_u64(23);
+            let alphabet = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789,:";
+            for _ in 0..500 {
+                let len = rng.gen_range(0..200);
+                let input: String = (0..len)
+                    .map(|_| alphabet[rng.gen_range(0..alphabet.len())] as char)
+                    .collect();
+                let decompressed = std::panic::catch_unwind(|| Map::decompress(&input));
+                assert!(decompressed.is_ok(), "decompress panicked on {:?}", input);
+                let parsed = std::panic::catch_unwind(|| Map::from_string(&input).is_ok());
+                assert!(parsed.is_ok(), "from_string panicked on {:?}", input);
+            }
+        }
     }
 }
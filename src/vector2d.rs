@@ -23,20 +23,52 @@ impl<T> Vector2D<T> {
         }
     }
 }
+impl<T: Neg<Output = T> + Copy> Vector2D<T> {
+    //Rotated 90 degrees clockwise, (1, 0) becomes (0, -1)
+    pub fn perpendicular_cw(&self) -> Self {
+        Vector2D::new(self.y, -self.x)
+    }
+
+    //Rotated 90 degrees counterclockwise, (1, 0) becomes (0, 1)
+    pub fn perpendicular_ccw(&self) -> Self {
+        Vector2D::new(-self.y, self.x)
+    }
+}
+
 impl<T: Div<Output = T> + Copy + Into<f32>> Vector2D<T> {
     pub fn length(&self) -> f32 {
         let length_squared = self.x.into().powi(2) + self.y.into().powi(2);
         length_squared.sqrt()
     }
 
+    //Zero length gives the zero vector, only non-finite components can give NaN.
+    //Use try_normalize when the zero case needs handling.
     pub fn normalize(&self) -> Vector2D<f32> {
         let length = self.length();
+        if length <= f32::EPSILON {
+            return Vector2D::zero();
+        }
         Vector2D {
             x: self.x.into() / length,
             y: self.y.into() / length,
         }
     }
 }
+impl Vector2D<f32> {
+    pub fn try_normalize(&self) -> Option<Vector2D<f32>> {
+        let length = self.length();
+        if length <= f32::EPSILON || !length.is_finite() {
+            return None;
+        }
+        Some(*self / length)
+    }
+
+    //True when both components differ by less than epsilon
+    pub fn approx_eq(&self, other: &Self, epsilon: f32) -> bool {
+        (self.x - other.x).abs() < epsilon && (self.y - other.y).abs() < epsilon
+    }
+}
+
 impl<T: fmt::Display> fmt::Display for Vector2D<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "({}, {})", self.x, self.y)
@@ -98,6 +130,7 @@ impl<T: Neg<Output = T>> Neg for Vector2D<T> {
     }
 }
 
+//No zero check, a float division by zero gives infinities or NaN
 impl<T> Div<T> for Vector2D<T>
 where
     T: Div<Output = T> + Copy,
@@ -122,6 +155,57 @@ mod tests {
         assert_eq!(vec2.length(), 0.0);
     }
 
+    #[test]
+    fn test_normalize_zero() {
+        let vec = Vector2D::new(0.0f32, 0.0).normalize();
+        assert_eq!((vec.x, vec.y), (0.0, 0.0));
+
+        let vec = Vector2D::new(0.0f32, -2.0).normalize();
+        assert_eq!((vec.x, vec.y), (0.0, -1.0));
+    }
+
+    #[test]
+    fn test_try_normalize() {
+        assert!(Vector2D::new(0.0f32, 0.0).try_normalize().is_none());
+        assert!(Vector2D::new(f32::EPSILON / 2.0, 0.0)
+            .try_normalize()
+            .is_none());
+        assert!(Vector2D::new(f32::NAN, 1.0).try_normalize().is_none());
+
+        let vec = Vector2D::new(3.0f32, -4.0).try_normalize().unwrap();
+        assert!(vec.approx_eq(&Vector2D::new(0.6, -0.8), 1e-6));
+        assert!((vec.length() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_perpendicular() {
+        let vec = Vector2D::new(1.0, 0.0);
+        assert_eq!(
+            (vec.perpendicular_cw().x, vec.perpendicular_cw().y),
+            (0.0, -1.0)
+        );
+        assert_eq!(
+            (vec.perpendicular_ccw().x, vec.perpendicular_ccw().y),
+            (0.0, 1.0)
+        );
+
+        let vec = Vector2D::new(3, -2);
+        let back = vec.perpendicular_cw().perpendicular_ccw();
+        assert_eq!((back.x, back.y), (3, -2));
+    }
+
+    #[test]
+    fn test_approx_eq() {
+        let vec = Vector2D::new(1.0f32, 2.0);
+        assert!(vec.approx_eq(&Vector2D::new(1.0, 2.0), 0.0001));
+        assert!(vec.approx_eq(&Vector2D::new(1.00009, 1.99991), 0.0001));
+        assert!(!vec.approx_eq(&Vector2D::new(1.0002, 2.0), 0.0001));
+        assert!(!vec.approx_eq(&Vector2D::new(1.0, 1.9998), 0.0001));
+        //Strictly less than epsilon
+        assert!(!vec.approx_eq(&Vector2D::new(1.5, 2.0), 0.5));
+        assert!(!vec.approx_eq(&Vector2D::new(f32::NAN, 2.0), 0.5));
+    }
+
     #[test]
     fn test_addition() {
         let vec1 = Vector2D::new(1, 2);